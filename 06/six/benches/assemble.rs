@@ -0,0 +1,84 @@
+// throughput of the assembler on a program shaped like VM translator output:
+//
+//     cargo bench
+//
+// prints the median of several runs for assembling and for writing the .hack image
+use std::io;
+use std::time::{Duration, Instant};
+
+use six::output::Format;
+
+// 33 words a block, as many as fit in the 32K words of ROM
+const BLOCKS: usize = 960;
+const RUNS: usize = 21;
+
+fn main() {
+    let source = generate(BLOCKS);
+    let lines = source.lines().count();
+    let megabytes = source.len() as f64 / 1_000_000.0;
+    println!(
+        "input: {} lines, {:.1} MB, {} blocks",
+        lines, megabytes, BLOCKS
+    );
+
+    let mut words = Vec::new();
+    let assemble = median(|| {
+        words = six::assemble(&source).expect("the generated program assembles");
+    });
+    report("assemble", assemble, lines, megabytes);
+
+    let write = median(|| {
+        Format::Hack
+            .write(&words, &mut io::sink())
+            .expect("writing to a sink cannot fail");
+    });
+    // 16 digits and a newline per word
+    let written = words.len() as f64 * 17.0 / 1_000_000.0;
+    report("write .hack", write, words.len(), written);
+    println!("output: {} words", words.len());
+}
+
+// push, pop, arithmetic and a comparison with jumps, with a label and variables per block
+fn generate(blocks: usize) -> String {
+    let mut source = String::new();
+    for block in 0..blocks {
+        source += &format!(
+            "// vm: Main.vm:{}: push constant {}\n\
+             @{}\nD=A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n\
+             @LCL\nD=M\n@2\nA=D+A\nD=M\n@SP\nA=M\nM=D\n@SP\nM=M+1\n\
+             @SP\nAM=M-1\nD=M\nA=A-1\nD=M-D\n@TRUE.{0}\nD;JEQ\n\
+             @var.{}\nM=D\n\
+             (TRUE.{0})\n@SP\nA=M-1\nM=-1\n\
+             @SCREEN+32*{}\nD=A\n@TRUE.{0}\n0;JMP\n",
+            block,
+            block,
+            block,
+            block % 200,
+            block % 256
+        );
+    }
+    source
+}
+
+fn median(mut run: impl FnMut()) -> Duration {
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    times[RUNS / 2]
+}
+
+fn report(name: &str, time: Duration, items: usize, megabytes: f64) {
+    let seconds = time.as_secs_f64();
+    println!(
+        "{:12} {:>8.1} ms  {:>10.0} lines/s  {:>6.1} MB/s",
+        name,
+        seconds * 1000.0,
+        items as f64 / seconds,
+        megabytes / seconds
+    );
+}
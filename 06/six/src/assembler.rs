@@ -0,0 +1,544 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::error::AsmError;
+use crate::expr::{self, ExprError};
+use crate::instruction::{check_symbol, Instruction};
+use crate::parser::{is_constant, split_first_word, Line, Parser};
+use crate::symbol_table::{Symbol, SymbolKind, SymbolTable};
+use crate::{code, data};
+
+// an A-instruction has 15 bits for its value, the top bit marks C-instructions
+pub const MAX_CONSTANT: u16 = 0x7fff;
+// the Hack memory map: 32K words of ROM, RAM up to the screen and the keyboard register
+pub const ROM_SIZE: usize = 32768;
+pub const SCREEN: usize = 16384;
+pub const KBD: usize = 24576;
+// RAM below this address holds SP, LCL, ... and R0-R15, data and variables follow
+pub const VARIABLE_START: usize = 16;
+// .equ values are full words, they only have to fit an A-instruction where used
+const MAX_EQU: i64 = 0xffff;
+
+// what the assembler can be asked for besides the machine code
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    // warn about variables referenced only once, usually a typo
+    pub warn_single_use: bool,
+    // keep address, binary and source of every instruction in `Assembly::listing`
+    pub listing: bool,
+    // run the peephole optimiser before labels are resolved
+    pub optimize: bool,
+    // keep where every instruction comes from in `Assembly::source_map`
+    pub source_map: bool,
+}
+
+pub struct Assembly {
+    pub words: Vec<u16>,
+    pub symbols: SymbolTable,
+    // RAM image from address 0, empty unless the program has data directives
+    pub ram: Vec<u16>,
+    // empty unless `Options::listing` is set
+    pub listing: Vec<String>,
+    // "address<TAB>file:line<TAB>vm origin" per ROM word, empty unless `Options::source_map` is set
+    pub source_map: Vec<String>,
+    // instructions removed by the optimiser in each file, empty unless `Options::optimize` is set
+    pub saved: Vec<(Rc<str>, usize)>,
+    pub warnings: Vec<AsmError>,
+}
+
+// the two passes over a parsed file. on failure every error and warning is returned
+pub fn assemble_program(parser: &mut Parser, options: &Options) -> Result<Assembly, Vec<AsmError>> {
+    let (mut assembly, errors) = analyze(parser, options);
+    if errors.iter().any(|e| e.is_error()) {
+        return Err(errors);
+    }
+    assembly.warnings = errors;
+    Ok(assembly)
+}
+
+// assemble as much as the errors allow, for tools that show a program while it is
+// edited. returns the partial assembly with every error and warning, instructions
+// that fail leave no word, so later addresses may be off until they are fixed
+pub fn analyze(parser: &mut Parser, options: &Options) -> (Assembly, Vec<AsmError>) {
+    // lines that fail to expand are dropped and reported with the rest
+    let mut errors = expand(parser);
+    let saved = if options.optimize {
+        parser.optimize()
+    } else {
+        Vec::new()
+    };
+
+    let mut symboltable = SymbolTable::new();
+    let mut words: Vec<u16> = Vec::new();
+
+    // every line is decoded once, both passes work on the decoded instructions
+    let mut program: Vec<(&Line, Instruction)> = Vec::with_capacity(parser.lines().len());
+    for line in parser.lines() {
+        match Instruction::decode(line) {
+            Ok(instruction) => program.push((line, instruction)),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    // first loop to collect symbol (Xxx), .equ definitions and the layout of data
+    let mut address_num: usize = 0;
+    let mut equs: Vec<(&Line, &str, &str)> = Vec::new();
+    // data from VARIABLE_START, .word values are filled in once all symbols are known
+    let mut ram: Vec<u16> = Vec::new();
+    let mut word_lines: Vec<(usize, &Line, &str)> = Vec::new();
+    for &(line, instruction) in &program {
+        let data_end = VARIABLE_START + ram.len();
+        match instruction {
+            Instruction::A(_) | Instruction::C { .. } => address_num += 1,
+            Instruction::Label(symbol) => {
+                let (number, column) = line.position_of(symbol);
+                let file = line.file.clone();
+                let entry = Symbol::new(address_num, SymbolKind::Label, file, number, column);
+                if let Err(previous) = symboltable.add_entry(symbol.to_string(), entry) {
+                    let message = match previous.kind {
+                        SymbolKind::Label => format!(
+                            "duplicate label '{}', first defined at {}:{}:{}",
+                            symbol, previous.file, previous.line, previous.column
+                        ),
+                        _ => redefinition("label", symbol, previous),
+                    };
+                    errors.push(line.error_at(symbol, message));
+                }
+            }
+            Instruction::Directive {
+                name: ".equ",
+                operands,
+            } => match equ_operands(operands) {
+                Some((name, value)) => match check_symbol(line, name) {
+                    Ok(()) => equs.push((line, name, value)),
+                    Err(e) => errors.push(e),
+                },
+                None => errors.push(line.error("expected '.equ NAME value'".to_string())),
+            },
+            Instruction::Directive {
+                name: ".data",
+                operands: "",
+            } => errors.push(line.error("expected '.data NAME'".to_string())),
+            Instruction::Directive {
+                name: ".data",
+                operands: name,
+            } => {
+                if let Err(e) = check_symbol(line, name) {
+                    errors.push(e);
+                } else {
+                    let (number, column) = line.position_of(name);
+                    let file = line.file.clone();
+                    let address = VARIABLE_START + ram.len();
+                    let entry = Symbol::new(address, SymbolKind::Data, file, number, column);
+                    if let Err(previous) = symboltable.add_entry(name.to_string(), entry) {
+                        errors.push(line.error_at(name, redefinition("data", name, previous)));
+                    }
+                }
+            }
+            Instruction::Directive {
+                name: ".word",
+                operands,
+            } => {
+                let count = data::words(operands).len();
+                if count == 0 {
+                    let message = "expected '.word value, ...'".to_string();
+                    errors.push(line.error(message));
+                }
+                word_lines.push((ram.len(), line, operands));
+                ram.resize(ram.len() + count, 0);
+            }
+            Instruction::Directive {
+                name: ".string",
+                operands,
+            } => match data::string(operands) {
+                Ok(values) => ram.extend(values),
+                Err(message) => {
+                    let part = if operands.is_empty() {
+                        &line.text
+                    } else {
+                        operands
+                    };
+                    errors.push(line.error_at(part, message));
+                }
+            },
+            Instruction::Directive { .. } => (),
+        }
+        if data_end <= SCREEN && VARIABLE_START + ram.len() > SCREEN {
+            let message = format!(
+                "data runs up to RAM[{}], into {}",
+                VARIABLE_START + ram.len() - 1,
+                memory_map(SCREEN).unwrap()
+            );
+            errors.push(line.error(message));
+        }
+    }
+
+    // constants may use any label and the constants defined before them
+    for &(line, name, value) in &equs {
+        let lookup = |symbol: &str| symboltable.get(symbol).map(|s| s.address as i64);
+        let value = match expr::evaluate(value, &lookup) {
+            Ok(result) if (0..=MAX_EQU).contains(&result) => result,
+            Ok(result) => {
+                let message = format!(
+                    "'{}' evaluates to {}, out of range (0..={})",
+                    value, result, MAX_EQU
+                );
+                errors.push(line.error_at(value, message));
+                continue;
+            }
+            Err(e) => {
+                errors.push(expr_error(line, value, e));
+                continue;
+            }
+        };
+
+        let (number, column) = line.position_of(name);
+        let file = line.file.clone();
+        let entry = Symbol::new(value as usize, SymbolKind::Constant, file, number, column);
+        if let Err(previous) = symboltable.add_entry(name.to_string(), entry) {
+            errors.push(line.error_at(name, redefinition("constant", name, previous)));
+        }
+    }
+
+    // .word values may use any label, constant or data name
+    for &(start, line, operands) in &word_lines {
+        for (index, value) in data::words(operands).into_iter().enumerate() {
+            let lookup = |symbol: &str| symboltable.get(symbol).map(|s| s.address as i64);
+            match expr::evaluate(value, &lookup) {
+                Ok(result) if (data::MIN_WORD..=data::MAX_WORD).contains(&result) => {
+                    ram[start + index] = result as u16;
+                }
+                Ok(result) => {
+                    let message = format!(
+                        "'{}' evaluates to {}, out of range ({}..={})",
+                        value,
+                        result,
+                        data::MIN_WORD,
+                        data::MAX_WORD
+                    );
+                    errors.push(line.error_at(value, message));
+                }
+                Err(e) => errors.push(expr_error(line, value, e)),
+            }
+        }
+    }
+    let variable_start = VARIABLE_START + ram.len();
+    if !ram.is_empty() {
+        ram.splice(0..0, vec![0; VARIABLE_START]);
+    }
+
+    // variables, in the order of their first reference, before any instruction is
+    // encoded so @y+1 sees y wherever @y stands
+    address_num = variable_start;
+    for &(line, instruction) in &program {
+        match instruction {
+            Instruction::A(symbol) if expr::is_symbol(symbol) && !symboltable.contains(symbol) => {
+                let (number, column) = line.position_of(symbol);
+                let file = line.file.clone();
+                let entry = Symbol::new(address_num, SymbolKind::Variable, file, number, column);
+                let _ = symboltable.add_entry(symbol.to_string(), entry);
+                if let Some(area) = memory_map(address_num) {
+                    let message = format!(
+                        "variable '{}' at RAM[{}] collides with {}",
+                        symbol, address_num, area
+                    );
+                    errors.push(line.error_at(symbol, message));
+                }
+                address_num += 1;
+            }
+            _ => (),
+        }
+    }
+
+    // second loop
+    words.reserve(program.len());
+    // how often each variable is referenced, with its first reference
+    let mut references: HashMap<String, (usize, AsmError)> = HashMap::new();
+    let mut listing: Vec<String> = Vec::new();
+    if options.listing {
+        listing.push(LISTING_HEADER.to_string());
+    }
+    let mut listing_file: Option<Rc<str>> = None;
+    let mut source_map: Vec<String> = Vec::new();
+    if options.source_map {
+        source_map.push(SOURCE_MAP_HEADER.to_string());
+    }
+    let mut overflow: Option<AsmError> = None;
+    for &(line, instruction) in &program {
+        let mut word: Option<u16> = None;
+
+        match instruction {
+            Instruction::A(symbol) if !expr::is_symbol(symbol) => {
+                let lookup = |name: &str| symboltable.get(name).map(|s| s.address as i64);
+                match expr::evaluate(symbol, &lookup) {
+                    Ok(value) if (0..=MAX_CONSTANT as i64).contains(&value) => {
+                        word = Some(value as u16);
+                    }
+                    Ok(value) => {
+                        let message = if is_constant(symbol) {
+                            format!("constant {} is out of range (0..={})", symbol, MAX_CONSTANT)
+                        } else {
+                            format!(
+                                "'{}' evaluates to {}, out of range (0..={})",
+                                symbol, value, MAX_CONSTANT
+                            )
+                        };
+                        errors.push(line.error_at(symbol, message));
+                    }
+                    Err(e) => errors.push(expr_error(line, symbol, e)),
+                }
+            }
+            Instruction::A(symbol) => {
+                let entry = symboltable.get(symbol).unwrap();
+                if options.warn_single_use && entry.kind == SymbolKind::Variable {
+                    match references.get_mut(symbol) {
+                        Some(reference) => reference.0 += 1,
+                        None => {
+                            let message = format!("variable '{}' is only referenced once", symbol);
+                            let warning = line.error_at(symbol, message).warning();
+                            references.insert(symbol.to_string(), (1, warning));
+                        }
+                    }
+                }
+                if entry.address > MAX_CONSTANT as usize {
+                    let message = format!(
+                        "'{}' is {}, out of range (0..={})",
+                        symbol, entry.address, MAX_CONSTANT
+                    );
+                    errors.push(line.error_at(symbol, message));
+                } else {
+                    word = Some(entry.address as u16)
+                }
+            }
+            Instruction::C { dest, comp, jump } => match encode_c(line, dest, comp, jump) {
+                Ok(code) => word = Some(code),
+                Err(e) => errors.push(e),
+            },
+            // handled in the first pass
+            Instruction::Directive {
+                name: ".equ" | ".data" | ".word" | ".string",
+                ..
+            } => (),
+            Instruction::Directive { name, .. } => {
+                let message = format!("unknown directive '{}'", name);
+                errors.push(line.error_at(name, message));
+            }
+            Instruction::Label(_) => (),
+        }
+
+        if options.listing {
+            // name each file as its lines start, included and linked files are interleaved
+            let file = line.origin().0;
+            if listing_file.as_ref() != Some(file) {
+                listing.push(format!("// {}", file));
+                listing_file = Some(file.clone());
+            }
+            if let Some(word) = word {
+                listing.push(listing_line(words.len(), word, line));
+            } else if !instruction.is_word() {
+                listing.push(listing_label(line));
+            }
+        }
+
+        if let (Some(_), true) = (word, options.source_map) {
+            source_map.push(source_map_line(words.len(), line));
+        }
+
+        if let Some(word) = word {
+            if words.len() == ROM_SIZE {
+                let message = format!("instruction does not fit, ROM holds {} words", ROM_SIZE);
+                overflow = Some(line.error(message));
+            }
+            words.push(word);
+        }
+    }
+
+    if options.warn_single_use {
+        for (count, warning) in references.into_values() {
+            if count == 1 {
+                errors.push(warning);
+            }
+        }
+    }
+
+    // the first instruction past the end of ROM, with the length of the whole program
+    if let Some(error) = overflow {
+        errors.push(error.with_note(format!("the program is {} words long", words.len())));
+    }
+
+    // linked and included files each keep their lines together
+    errors.sort_by(|a, b| (&a.file, a.line, a.columns).cmp(&(&b.file, b.line, b.columns)));
+    let assembly = Assembly {
+        words,
+        symbols: symboltable,
+        ram,
+        listing,
+        source_map,
+        saved,
+        warnings: Vec::new(),
+    };
+    (assembly, errors)
+}
+
+// includes, macros and pseudo-instructions, then $-labels
+fn expand(parser: &mut Parser) -> Vec<AsmError> {
+    let mut errors = parser.expand_includes();
+    errors.extend(parser.expand_macros());
+    errors.extend(parser.expand_pseudo());
+    parser.localize_labels();
+    errors
+}
+
+const LISTING_HEADER: &str = " ROM  binary            hex    line  source";
+
+const SOURCE_MAP_HEADER: &str = "# address\tasm\tvm";
+
+// 12	Main.asm:40	Main.vm:3: push constant 1
+fn source_map_line(address: usize, line: &Line) -> String {
+    let (file, number) = line.origin();
+    match &line.vm {
+        Some(vm) => format!("{}\t{}:{}\t{}", address, file, number, vm),
+        None => format!("{}\t{}:{}", address, file, number),
+    }
+}
+
+// 0004  1110101010000111  EA87     12  0;JMP // loop
+fn listing_line(address: usize, word: u16, line: &Line) -> String {
+    format!(
+        "{:04}  {:016b}  {:04X}  {:>5}  {}",
+        address,
+        word,
+        word,
+        line.origin().1,
+        listing_source(line)
+    )
+}
+
+// labels and directives take no ROM word, they are listed without an address
+fn listing_label(line: &Line) -> String {
+    format!("{:30}{:>5}  {}", "", line.origin().1, listing_source(line))
+}
+
+// expanded lines are indented under their macro call and name the body line,
+// lines of a pseudo-instruction name the pseudo-instruction
+fn listing_source(line: &Line) -> String {
+    match &line.expansion {
+        Some(expansion) => format!(
+            "  {:<24} // {} line {}",
+            line.text, expansion.name, line.number
+        ),
+        None if !written_at_column(line) => {
+            format!("  {:<24} // {}", line.text, line.source.trim())
+        }
+        None => line.source.trim_end().to_string(),
+    }
+}
+
+// whether the file has the line as written where it starts, the lines of a
+// pseudo-instruction are not written anywhere
+fn written_at_column(line: &Line) -> bool {
+    let rest = line
+        .column
+        .checked_sub(1)
+        .and_then(|start| line.source.get(start..));
+    rest.is_some_and(|rest| rest.starts_with(&line.written()))
+}
+
+// dest=comp;jump -> 111accccccdddjjj, rejecting any field the code module does not know
+fn encode_c(
+    line: &Line,
+    dest: Option<&str>,
+    comp: &str,
+    jump: Option<&str>,
+) -> Result<u16, AsmError> {
+    if comp.is_empty() {
+        return Err(line.error("missing comp part".to_string()));
+    }
+    let comp_bits = match code::comp(comp) {
+        Some(bits) => bits,
+        None => return Err(line.error_at(comp, format!("unknown comp mnemonic '{}'", comp))),
+    };
+
+    let dest_bits = match dest {
+        None => "000",
+        Some("") => return Err(line.error("missing dest before '='".to_string())),
+        Some(dest) => match code::dest(dest) {
+            Some(bits) => bits,
+            None => return Err(line.error_at(dest, format!("unknown dest mnemonic '{}'", dest))),
+        },
+    };
+
+    let jump_bits = match jump {
+        None => "000",
+        Some("") => return Err(line.error("missing jump after ';'".to_string())),
+        Some(jump) => match code::jump(jump) {
+            Some(bits) => bits,
+            None => return Err(line.error_at(jump, format!("unknown jump mnemonic '{}'", jump))),
+        },
+    };
+
+    let bits = |field: &str| u16::from_str_radix(field, 2).unwrap();
+    Ok(0b111 << 13 | bits(comp_bits) << 6 | bits(dest_bits) << 3 | bits(jump_bits))
+}
+
+impl Assembly {
+    // ROM and RAM usage: "ROM 30/32768 words (0.1%), RAM 2/16368 words (0.0%)"
+    pub fn usage(&self) -> String {
+        let variables = self
+            .symbols
+            .sorted()
+            .iter()
+            .filter(|(_, symbol)| symbol.kind == SymbolKind::Variable)
+            .count();
+        let ram = self.ram.len().saturating_sub(VARIABLE_START) + variables;
+        let percent = |used: usize, size: usize| used as f64 * 100.0 / size as f64;
+        format!(
+            "ROM {}/{} words ({:.1}%), RAM {}/{} words ({:.1}%)",
+            self.words.len(),
+            ROM_SIZE,
+            percent(self.words.len(), ROM_SIZE),
+            ram,
+            SCREEN - VARIABLE_START,
+            percent(ram, SCREEN - VARIABLE_START)
+        )
+    }
+}
+
+// the memory mapped device at a RAM address, if any
+fn memory_map(address: usize) -> Option<String> {
+    if address < SCREEN {
+        None
+    } else if address < KBD {
+        Some(format!("the screen ({}..={})", SCREEN, KBD - 1))
+    } else if address == KBD {
+        Some(format!("the keyboard ({})", KBD))
+    } else {
+        Some(format!("no memory, RAM ends at {}", KBD))
+    }
+}
+
+// a symbol defined again by a label, .equ or .data
+fn redefinition(what: &str, name: &str, previous: &Symbol) -> String {
+    match previous.kind {
+        SymbolKind::Predefined => format!("{} '{}' shadows a predefined symbol", what, name),
+        _ => format!(
+            "'{}' is already defined at {}:{}:{}",
+            name, previous.file, previous.line, previous.column
+        ),
+    }
+}
+
+// "NAME value" of an .equ directive
+fn equ_operands(operands: &str) -> Option<(&str, &str)> {
+    match split_first_word(operands) {
+        (_, "") => None,
+        pair => Some(pair),
+    }
+}
+
+// point at the part of `text`, a slice of the line, the expression error is about
+fn expr_error(line: &Line, text: &str, e: ExprError) -> AsmError {
+    let end = e.end.min(text.len());
+    let start = e.start.min(end);
+    line.error_at(&text[start..end], e.message)
+}
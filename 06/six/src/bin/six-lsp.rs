@@ -0,0 +1,32 @@
+// six-lsp, the language server for Hack assembly: requests are read from stdin
+// and answered on stdout, as editors start language servers
+use std::io::{self, BufReader};
+use std::process;
+
+use six::lsp::{self, Server};
+
+fn main() {
+    let mut input = BufReader::new(io::stdin().lock());
+    let mut output = io::stdout().lock();
+    let mut server = Server::new();
+
+    loop {
+        let message = match lsp::read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("six-lsp: {}", e);
+                process::exit(1);
+            }
+        };
+        for reply in server.handle(&message) {
+            if let Err(e) = lsp::write_message(&mut output, &reply) {
+                eprintln!("six-lsp: {}", e);
+                process::exit(1);
+            }
+        }
+        if let Some(code) = server.exit_code() {
+            process::exit(code);
+        }
+    }
+}
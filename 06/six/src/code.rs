@@ -0,0 +1,100 @@
+// code modele, mnemonic <-> bit tables of the C-instruction fields
+pub const DEST: [(&str, &str); 7] = [
+    ("M", "001"),
+    ("D", "010"),
+    ("MD", "011"),
+    ("A", "100"),
+    ("AM", "101"),
+    ("AD", "110"),
+    ("AMD", "111"),
+];
+
+pub const COMP: [(&str, &str); 28] = [
+    ("0", "0101010"),
+    ("1", "0111111"),
+    ("-1", "0111010"),
+    ("D", "0001100"),
+    ("A", "0110000"),
+    ("!D", "0001101"),
+    ("!A", "0110001"),
+    ("-D", "0001111"),
+    ("-A", "0110011"),
+    ("D+1", "0011111"),
+    ("A+1", "0110111"),
+    ("D-1", "0001110"),
+    ("A-1", "0110010"),
+    ("D+A", "0000010"),
+    ("D-A", "0010011"),
+    ("A-D", "0000111"),
+    ("D&A", "0000000"),
+    ("D|A", "0010101"),
+    ("M", "1110000"),
+    ("!M", "1110001"),
+    ("-M", "1110011"),
+    ("M+1", "1110111"),
+    ("M-1", "1110010"),
+    ("D+M", "1000010"),
+    ("D-M", "1010011"),
+    ("M-D", "1000111"),
+    ("D&M", "1000000"),
+    ("D|M", "1010101"),
+];
+
+pub const JUMP: [(&str, &str); 7] = [
+    ("JGT", "001"),
+    ("JEQ", "010"),
+    ("JGE", "011"),
+    ("JLT", "100"),
+    ("JNE", "101"),
+    ("JLE", "110"),
+    ("JMP", "111"),
+];
+
+fn lookup(table: &[(&str, &'static str)], mnemonic: &str) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(name, _)| *name == mnemonic)
+        .map(|(_, bits)| *bits)
+}
+
+// the registers may be written in any order (DM, MD, ADM, ...) but only once each
+pub fn dest(mnemonic: &str) -> Option<&'static str> {
+    let mut bits: usize = 0;
+    for register in mnemonic.chars() {
+        let bit = match register {
+            'A' => 0b100,
+            'D' => 0b010,
+            'M' => 0b001,
+            _ => return None,
+        };
+        if bits & bit != 0 {
+            return None;
+        }
+        bits |= bit;
+    }
+    let index = bits.checked_sub(1)?;
+    DEST.get(index).map(|(_, bits)| *bits)
+}
+
+// commutative operations are also accepted with swapped operands (A+D, M&D, ...)
+pub fn comp(mnemonic: &str) -> Option<&'static str> {
+    if let Some(bits) = lookup(&COMP, mnemonic) {
+        return Some(bits);
+    }
+
+    let op = mnemonic.find(['+', '&', '|'])?;
+    if op == 0 {
+        return None;
+    }
+    let swapped = format!(
+        "{}{}{}",
+        &mnemonic[op + 1..],
+        &mnemonic[op..op + 1],
+        &mnemonic[..op]
+    );
+    lookup(&COMP, &swapped)
+}
+
+pub fn jump(mnemonic: &str) -> Option<&'static str> {
+    lookup(&JUMP, mnemonic)
+}
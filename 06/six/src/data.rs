@@ -0,0 +1,50 @@
+// data directives filling the RAM image the emulator preloads.
+//
+//     .data TABLE          // TABLE names the next free RAM address
+//     .word 1, 2, LOOP+1   // one word per value
+//     .data GREETING
+//     .string "hi\n"       // one word per character and a terminating 0
+//
+// data is laid out from address 16 in source order, variables are allocated after it
+
+// values of .word are full words, negative ones are stored in two's complement
+pub const MIN_WORD: i64 = -0x8000;
+pub const MAX_WORD: i64 = 0xffff;
+
+// the comma separated values of a .word directive
+pub fn words(operands: &str) -> Vec<&str> {
+    if operands.is_empty() {
+        return Vec::new();
+    }
+    operands.split(',').map(|value| value.trim()).collect()
+}
+
+// the characters of a "quoted" string followed by 0. escapes are \n, \t, \0, \" and \\
+pub fn string(operands: &str) -> Result<Vec<u16>, String> {
+    let text = match operands.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(text) if operands.len() >= 2 => text,
+        _ => return Err("expected a string in double quotes".to_string()),
+    };
+
+    let mut values = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some(other) => return Err(format!("unknown escape '\\{}'", other)),
+                None => return Err("string ends with '\\'".to_string()),
+            },
+            '"' => return Err("unescaped '\"' in string".to_string()),
+            c if !c.is_ascii() => return Err(format!("character '{}' is not ASCII", c)),
+            c => c,
+        };
+        values.push(c as u16);
+    }
+    values.push(0);
+    Ok(values)
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::code;
+use crate::error::AsmError;
+use crate::parser::is_constant;
+
+// names read back from a .sym file written with -s
+#[derive(Default)]
+pub struct SymbolMap {
+    // ROM address -> labels defined there
+    labels: HashMap<u16, Vec<String>>,
+    // RAM address -> variable, data or predefined name
+    variables: HashMap<u16, String>,
+}
+
+impl SymbolMap {
+    pub fn load(path: &Path) -> Result<SymbolMap, AsmError> {
+        let filename = path.display().to_string();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => return Err(AsmError::new(&filename, format!("cannot open file: {}", e))),
+        };
+        SymbolMap::parse(&text, &filename)
+    }
+
+    // the "name kind address" lines of a .sym file, `filename` is used in errors
+    pub fn parse(text: &str, filename: &str) -> Result<SymbolMap, AsmError> {
+        let mut map = SymbolMap::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let address = match fields.as_slice() {
+                [_, _, address] => address.parse::<u16>().ok(),
+                _ => None,
+            };
+            let address = match address {
+                Some(address) => address,
+                None => {
+                    let message = "expected 'name kind address'".to_string();
+                    let columns = (1, line.len() + 1);
+                    return Err(AsmError::at(filename, number + 1, columns, message));
+                }
+            };
+
+            let name = fields[0].to_string();
+            match fields[1] {
+                "label" => map.labels.entry(address).or_default().push(name),
+                "variable" | "data" => {
+                    map.variables.insert(address, name);
+                }
+                // SP, LCL, ARG, THIS and THAT rather than the R0..R4 they share an address with
+                "predefined" => {
+                    let register = name.strip_prefix('R').is_some_and(is_constant);
+                    if !register || !map.variables.contains_key(&address) {
+                        map.variables.insert(address, name);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        for names in map.labels.values_mut() {
+            names.sort();
+        }
+        Ok(map)
+    }
+}
+
+// turn the words of a .hack file back into assembly, one instruction per line.
+// words that are not legal instructions are kept as comments and reported as warnings
+pub fn disassemble(
+    source: &str,
+    filename: &str,
+    symbols: &SymbolMap,
+) -> Result<(String, Vec<AsmError>), Vec<AsmError>> {
+    let mut words: Vec<(usize, u16)> = Vec::new();
+    let mut errors: Vec<AsmError> = Vec::new();
+
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let word = if line.len() == 16 && line.bytes().all(|b| b == b'0' || b == b'1') {
+            u16::from_str_radix(line, 2).ok()
+        } else {
+            None
+        };
+        match word {
+            Some(word) => words.push((number + 1, word)),
+            None => {
+                let message = "expected 16 binary digits".to_string();
+                let columns = (1, line.len() + 1);
+                errors.push(AsmError::at(filename, number + 1, columns, message).with_source(line));
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut lines: Vec<String> = Vec::new();
+    for (address, &(number, word)) in words.iter().enumerate() {
+        if let Some(names) = symbols.labels.get(&(address as u16)) {
+            for name in names {
+                lines.push(format!("({})", name));
+            }
+        }
+
+        let next = words.get(address + 1).map(|&(_, next)| next);
+        match decode(word, symbols, next) {
+            Ok(instruction) => lines.push(instruction),
+            Err(reason) => {
+                let binary = format!("{:016b}", word);
+                let message = format!("illegal instruction {}: {}", binary, reason);
+                errors.push(AsmError::at(filename, number, (1, 17), message).warning());
+                lines.push(format!("// illegal: {} ({})", binary, reason));
+            }
+        }
+    }
+
+    Ok((lines.join("\n") + "\n", errors))
+}
+
+fn is_c_instruction(word: u16) -> bool {
+    word >> 13 == 0b111
+}
+
+// `next` is the word after this one, what it does with A tells what a constant
+// stands for
+fn decode(word: u16, symbols: &SymbolMap, next: Option<u16>) -> Result<String, String> {
+    if word >> 15 == 0 {
+        // a ROM address when the next instruction jumps to it, a RAM address when it
+        // reads or writes M, a plain number when A is used as data
+        let name = match next.filter(|&next| is_c_instruction(next)) {
+            Some(next) if next & 0b111 != 0 => {
+                symbols.labels.get(&word).and_then(|names| names.first())
+            }
+            Some(next) if next & (1 << 12) != 0 || next & (1 << 3) != 0 => {
+                symbols.variables.get(&word)
+            }
+            _ => None,
+        };
+        return Ok(match name {
+            Some(name) => format!("@{}", name),
+            None => format!("@{}", word),
+        });
+    }
+
+    if !is_c_instruction(word) {
+        return Err("C-instruction without the 111 prefix".to_string());
+    }
+
+    let comp_bits = format!("{:07b}", (word >> 6) & 0b111_1111);
+    let comp = match code::COMP.iter().find(|(_, bits)| *bits == comp_bits) {
+        Some((comp, _)) => comp,
+        None => return Err(format!("unknown comp bits {}", comp_bits)),
+    };
+
+    let mut instruction = String::new();
+    let dest = (word >> 3) & 0b111;
+    if dest != 0 {
+        instruction += code::DEST[dest as usize - 1].0;
+        instruction += "=";
+    }
+    instruction += comp;
+    let jump = word & 0b111;
+    if jump != 0 {
+        instruction += ";";
+        instruction += code::JUMP[jump as usize - 1].0;
+    }
+    Ok(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOLS: &str = "# name kind address\n\
+                           LOOP label 2\n\
+                           END label 25\n\
+                           N constant 5\n\
+                           x variable 16\n\
+                           y variable 25\n\
+                           R0 predefined 0\n\
+                           SP predefined 0\n\
+                           LCL predefined 1\n\
+                           R1 predefined 1\n\
+                           R7 predefined 7\n\
+                           SCREEN predefined 16384\n";
+
+    fn symbols() -> SymbolMap {
+        SymbolMap::parse(SYMBOLS, "test.sym").unwrap()
+    }
+
+    // `word` disassembled in front of the C-instruction `next`
+    fn before(word: u16, next: &str) -> String {
+        let next = crate::assemble(next).unwrap()[0];
+        decode(word, &symbols(), Some(next)).unwrap()
+    }
+
+    #[test]
+    fn load_names() {
+        let map = symbols();
+        assert_eq!(map.labels[&2], ["LOOP"]);
+        assert_eq!(map.variables[&16], "x");
+        assert_eq!(map.variables[&0], "SP");
+        assert_eq!(map.variables[&1], "LCL");
+        assert_eq!(map.variables[&7], "R7");
+        assert_eq!(map.variables[&16384], "SCREEN");
+        // constants name no address
+        assert!(!map.variables.contains_key(&5));
+    }
+
+    #[test]
+    fn load_rejects_bad_lines() {
+        let error = SymbolMap::parse("x variable\n", "test.sym").err().unwrap();
+        assert_eq!(error.line, 1);
+        assert!(SymbolMap::parse("x variable sixteen\n", "test.sym").is_err());
+    }
+
+    #[test]
+    fn names_for_memory_access() {
+        assert_eq!(before(16, "M=D"), "@x");
+        assert_eq!(before(0, "AM=M-1"), "@SP");
+        assert_eq!(before(16384, "D=M"), "@SCREEN");
+    }
+
+    #[test]
+    fn labels_for_jumps() {
+        assert_eq!(before(2, "0;JMP"), "@LOOP");
+        assert_eq!(before(25, "D;JGT"), "@END");
+    }
+
+    #[test]
+    fn numbers_for_data() {
+        assert_eq!(before(7, "D=A"), "@7");
+        assert_eq!(before(16384, "D=A"), "@16384");
+        assert_eq!(before(10, "A=A+1"), "@10");
+        assert_eq!(before(25, "D=D+A"), "@25");
+        assert_eq!(decode(16, &symbols(), None).unwrap(), "@16");
+        assert_eq!(decode(16, &symbols(), Some(17)).unwrap(), "@16");
+    }
+
+    #[test]
+    fn c_instructions() {
+        let none = SymbolMap::default();
+        assert_eq!(decode(0b1110001100001000, &none, None).unwrap(), "M=D");
+        assert_eq!(decode(0b1110101010000111, &none, None).unwrap(), "0;JMP");
+        assert!(decode(0b1000001100001000, &none, None).is_err());
+    }
+}
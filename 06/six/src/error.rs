@@ -0,0 +1,129 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// an error or warning found while assembling, pointing back at the original source
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub file: String,
+    // 1-based line number in the source file, 0 if the error is not tied to a line
+    pub line: usize,
+    // 1-based column range [start, end) on that line
+    pub columns: (usize, usize),
+    pub message: String,
+    pub severity: Severity,
+    // the source line, echoed under the message
+    pub source: Option<String>,
+    // extra context such as the macro call a line was expanded from
+    pub note: Option<String>,
+}
+
+impl AsmError {
+    pub fn new(file: &str, message: String) -> AsmError {
+        AsmError {
+            file: file.to_string(),
+            line: 0,
+            columns: (0, 0),
+            message,
+            severity: Severity::Error,
+            source: None,
+            note: None,
+        }
+    }
+
+    pub fn at(file: &str, line: usize, columns: (usize, usize), message: String) -> AsmError {
+        AsmError {
+            file: file.to_string(),
+            line,
+            columns,
+            message,
+            severity: Severity::Error,
+            source: None,
+            note: None,
+        }
+    }
+
+    pub fn with_source(mut self, source: &str) -> AsmError {
+        self.source = Some(source.to_string());
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> AsmError {
+        self.note = Some(note);
+        self
+    }
+
+    pub fn warning(mut self) -> AsmError {
+        self.severity = Severity::Warning;
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}: {}: {}", self.file, self.severity, self.message);
+        }
+
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.file, self.line, self.columns.0, self.severity, self.message
+        )?;
+
+        if let Some(source) = &self.source {
+            let gutter = self.line.to_string().len();
+            let width = self.columns.1.saturating_sub(self.columns.0).max(1);
+            write!(
+                f,
+                "\n{:>gutter$} | {}\n{:>gutter$} | {:>pad$}{}",
+                self.line,
+                source,
+                "",
+                "",
+                "^".repeat(width),
+                gutter = gutter,
+                pad = self.columns.0.saturating_sub(1),
+            )?;
+        }
+
+        if let Some(note) = &self.note {
+            write!(f, "\n  = note: {}", note)?;
+        }
+
+        Ok(())
+    }
+}
+
+// print every diagnostic of a file followed by a count
+pub fn report(errors: &[AsmError]) {
+    for error in errors {
+        eprintln!("{}", error);
+    }
+
+    let count = errors.iter().filter(|e| e.is_error()).count();
+    let warnings = errors.len() - count;
+    if count > 0 {
+        eprintln!("{} error(s)", count);
+    }
+    if warnings > 0 {
+        eprintln!("{} warning(s)", warnings);
+    }
+}
@@ -0,0 +1,286 @@
+// constant expressions in A-instructions and .equ, such as @SCREEN+32*row or @0x4000.
+//
+//     expr    = term (('+' | '-') term)*
+//     term    = unary (('*' | '/') unary)*
+//     unary   = '-' unary | primary
+//     primary = number | symbol | '(' expr ')'
+//
+// numbers are decimal, 0x hexadecimal or 0b binary
+#[derive(Debug, PartialEq)]
+pub struct ExprError {
+    pub message: String,
+    // byte range of the offending part of the expression
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    Number(i64),
+    Symbol(&'a str),
+    Op(char),
+}
+
+pub fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.$:".contains(c)
+}
+
+// a plain symbol, the only A-instruction operand that may create a variable
+pub fn is_symbol(text: &str) -> bool {
+    !text.is_empty()
+        && !text.starts_with(|c: char| c.is_ascii_digit())
+        && text.chars().all(is_symbol_char)
+}
+
+// evaluate `text`, looking symbols up with `lookup`
+pub fn evaluate(text: &str, lookup: &dyn Fn(&str) -> Option<i64>) -> Result<i64, ExprError> {
+    let tokens = tokenize(text)?;
+    let mut parser = ExprParser {
+        tokens,
+        position: 0,
+        lookup,
+        length: text.len(),
+    };
+    let value = parser.expr()?;
+    if let Some((_, start, end)) = parser.tokens.get(parser.position) {
+        return Err(error("unexpected token", *start, *end));
+    }
+    Ok(value)
+}
+
+// rewrite every symbol in `text` for which `rename` returns a new name
+pub fn rename_symbols(text: &str, rename: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut output = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if is_symbol_char(c) {
+            let end = rest
+                .find(|c: char| !is_symbol_char(c))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            match rename(word) {
+                Some(name) if !c.is_ascii_digit() => output += &name,
+                _ => output += word,
+            }
+            rest = &rest[end..];
+        } else {
+            output.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    output
+}
+
+fn error(message: &str, start: usize, end: usize) -> ExprError {
+    ExprError {
+        message: message.to_string(),
+        start,
+        end,
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<(Token<'_>, usize, usize)>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut position = 0;
+
+    while let Some(c) = text[position..].chars().next() {
+        if c.is_whitespace() {
+            position += c.len_utf8();
+            continue;
+        }
+
+        if "+-*/()".contains(c) {
+            tokens.push((Token::Op(c), position, position + 1));
+            position += 1;
+            continue;
+        }
+
+        if !is_symbol_char(c) {
+            let message = format!("invalid character '{}'", c);
+            return Err(error(&message, position, position + c.len_utf8()));
+        }
+
+        let end = text[position..]
+            .find(|c: char| !is_symbol_char(c))
+            .map_or(text.len(), |len| position + len);
+        let word = &text[position..end];
+        let token = if c.is_ascii_digit() {
+            match parse_number(word) {
+                Some(value) => Token::Number(value),
+                None => {
+                    let message = format!("invalid number '{}'", word);
+                    return Err(error(&message, position, end));
+                }
+            }
+        } else {
+            Token::Symbol(word)
+        };
+        tokens.push((token, position, end));
+        position = end;
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(word: &str) -> Option<i64> {
+    if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = word.strip_prefix("0b").or_else(|| word.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()
+    } else if word.bytes().all(|b| b.is_ascii_digit()) {
+        word.parse::<i64>().ok()
+    } else {
+        None
+    }
+}
+
+struct ExprParser<'a, 'b> {
+    tokens: Vec<(Token<'a>, usize, usize)>,
+    position: usize,
+    lookup: &'b dyn Fn(&str) -> Option<i64>,
+    // where to point errors about a missing operand
+    length: usize,
+}
+
+impl ExprParser<'_, '_> {
+    fn peek_op(&self) -> Option<char> {
+        match self.tokens.get(self.position) {
+            Some((Token::Op(op), _, _)) => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self) -> Result<i64, ExprError> {
+        let mut value = self.term()?;
+        while let Some(op @ ('+' | '-')) = self.peek_op() {
+            let (start, end) = self.span();
+            self.position += 1;
+            let rhs = self.term()?;
+            let result = if op == '+' {
+                value.checked_add(rhs)
+            } else {
+                value.checked_sub(rhs)
+            };
+            value = result.ok_or_else(|| error("arithmetic overflow", start, end))?;
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<i64, ExprError> {
+        let mut value = self.unary()?;
+        while let Some(op @ ('*' | '/')) = self.peek_op() {
+            let (start, end) = self.span();
+            self.position += 1;
+            let rhs = self.unary()?;
+            let result = if op == '*' {
+                value.checked_mul(rhs)
+            } else if rhs == 0 {
+                return Err(error("division by zero", start, end));
+            } else {
+                value.checked_div(rhs)
+            };
+            value = result.ok_or_else(|| error("arithmetic overflow", start, end))?;
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, ExprError> {
+        if self.peek_op() == Some('-') {
+            let (start, end) = self.span();
+            self.position += 1;
+            let value = self.unary()?;
+            return value
+                .checked_neg()
+                .ok_or_else(|| error("arithmetic overflow", start, end));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<i64, ExprError> {
+        let (token, start, end) = match self.tokens.get(self.position) {
+            Some(token) => token.clone(),
+            None => return Err(error("missing operand", self.length, self.length + 1)),
+        };
+        self.position += 1;
+
+        match token {
+            Token::Number(value) => Ok(value),
+            Token::Symbol(name) => match (self.lookup)(name) {
+                Some(value) => Ok(value),
+                None => {
+                    let message = format!("undefined symbol '{}' in expression", name);
+                    Err(error(&message, start, end))
+                }
+            },
+            Token::Op('(') => {
+                let value = self.expr()?;
+                if self.peek_op() != Some(')') {
+                    return Err(error("missing ')'", start, end));
+                }
+                self.position += 1;
+                Ok(value)
+            }
+            Token::Op(op) => Err(error(&format!("unexpected '{}'", op), start, end)),
+        }
+    }
+
+    fn span(&self) -> (usize, usize) {
+        let (_, start, end) = &self.tokens[self.position];
+        (*start, *end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(symbol: &str) -> Option<i64> {
+        match symbol {
+            "SCREEN" => Some(16384),
+            "row" => Some(3),
+            _ => None,
+        }
+    }
+
+    fn eval(text: &str) -> Result<i64, ExprError> {
+        evaluate(text, &lookup)
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(eval("42"), Ok(42));
+        assert_eq!(eval("0x4000"), Ok(16384));
+        assert_eq!(eval("0b101"), Ok(5));
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(eval("SCREEN+32*row"), Ok(16480));
+        assert_eq!(eval("(1+2)*3"), Ok(9));
+        assert_eq!(eval("10-4-3"), Ok(3));
+        assert_eq!(eval("-2*-3"), Ok(6));
+        assert_eq!(eval("7/2"), Ok(3));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(eval("1/0").unwrap_err().message, "division by zero");
+        assert_eq!(eval("1+").unwrap_err().message, "missing operand");
+        assert_eq!(eval("(1+2").unwrap_err().message, "missing ')'");
+        assert_eq!(eval("1 2").unwrap_err().message, "unexpected token");
+        assert_eq!(
+            eval("0x7fffffffffffffff*2").unwrap_err().message,
+            "arithmetic overflow"
+        );
+        let error = eval("row+y").unwrap_err();
+        assert_eq!(error.message, "undefined symbol 'y' in expression");
+        assert_eq!((error.start, error.end), (4, 5));
+    }
+
+    #[test]
+    fn rename() {
+        let rename = |symbol: &str| Some(format!("F.{}", symbol));
+        assert_eq!(rename_symbols("$L+1", &rename), "F.$L+1");
+        assert_eq!(rename_symbols("2*x", &rename), "2*F.x");
+    }
+}
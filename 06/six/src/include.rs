@@ -0,0 +1,80 @@
+// .include "file.asm" pulls the lines of another file in place of the directive.
+// the path is relative to the file containing the directive
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::AsmError;
+use crate::parser::{read_lines, Line};
+
+// a directive that fails is dropped, the other lines are kept
+pub fn expand(code: Vec<Line>) -> (Vec<Line>, Vec<AsmError>) {
+    let mut output = Vec::new();
+    let mut errors = Vec::new();
+    expand_into(code, &[], &mut output, &mut errors);
+
+    (output, errors)
+}
+
+// `stack` holds the files that include the one `code` comes from
+fn expand_into(
+    code: Vec<Line>,
+    stack: &[PathBuf],
+    output: &mut Vec<Line>,
+    errors: &mut Vec<AsmError>,
+) {
+    for line in code {
+        let rest = match line.text.strip_prefix(".include") {
+            Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => rest.trim(),
+            _ => {
+                output.push(line);
+                continue;
+            }
+        };
+
+        let name = match rest.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            Some(name) if !name.is_empty() => name,
+            _ => {
+                let message = "expected .include \"file.asm\"".to_string();
+                errors.push(line.error_at(&line.text, message));
+                continue;
+            }
+        };
+
+        let current = Path::new(&*line.file);
+        let path = current.parent().unwrap_or(Path::new("")).join(name);
+        let canonical = match path.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(e) => {
+                let message = format!("cannot include {}: {}", path.display(), e);
+                errors.push(line.error_at(rest, message));
+                continue;
+            }
+        };
+
+        let mut chain = stack.to_vec();
+        chain.push(current.canonicalize().unwrap_or(current.to_path_buf()));
+        if chain.contains(&canonical) {
+            let cycle: Vec<String> = chain
+                .iter()
+                .skip_while(|file| **file != canonical)
+                .chain(Some(&canonical))
+                .map(|file| file.display().to_string())
+                .collect();
+            let message = format!("include cycle: {}", cycle.join(" -> "));
+            errors.push(line.error_at(rest, message));
+            continue;
+        }
+
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                let message = format!("cannot include {}: {}", path.display(), e);
+                errors.push(line.error_at(rest, message));
+                continue;
+            }
+        };
+
+        let lines = read_lines(&path.display().to_string(), &source);
+        expand_into(lines, &chain, output, errors);
+    }
+}
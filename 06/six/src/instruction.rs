@@ -0,0 +1,96 @@
+// a line decoded once into the parts the passes need. the parts are slices of the
+// line's text, so errors can still point at them
+use crate::error::AsmError;
+use crate::parser::{is_constant, split_first_word, Line};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction<'a> {
+    // @value, a symbol or a constant expression
+    A(&'a str),
+    // dest=comp;jump, dest and jump are None without '=' or ';'
+    C {
+        dest: Option<&'a str>,
+        comp: &'a str,
+        jump: Option<&'a str>,
+    },
+    // (name)
+    Label(&'a str),
+    // .name operands
+    Directive {
+        name: &'a str,
+        operands: &'a str,
+    },
+}
+
+impl<'a> Instruction<'a> {
+    pub fn decode(line: &'a Line) -> Result<Instruction<'a>, AsmError> {
+        let text = line.text.as_str();
+
+        if let Some(value) = text.strip_prefix('@') {
+            let value = value.trim();
+            if value.is_empty() {
+                return Err(line.error("missing value after '@'".to_string()));
+            }
+            return Ok(Instruction::A(value));
+        }
+
+        if text.starts_with('(') {
+            return decode_label(line);
+        }
+
+        if text.starts_with('.') {
+            let (name, operands) = split_first_word(text);
+            return Ok(Instruction::Directive { name, operands });
+        }
+
+        let (dest, rest) = match text.find('=') {
+            Some(index) => (Some(text[..index].trim()), &text[index + 1..]),
+            None => (None, text),
+        };
+        let (comp, jump) = match rest.find(';') {
+            Some(index) => (rest[..index].trim(), Some(rest[index + 1..].trim())),
+            None => (rest.trim(), None),
+        };
+        Ok(Instruction::C { dest, comp, jump })
+    }
+
+    // whether the instruction takes a word of ROM
+    pub fn is_word(&self) -> bool {
+        matches!(self, Instruction::A(_) | Instruction::C { .. })
+    }
+}
+
+fn decode_label(line: &Line) -> Result<Instruction<'_>, AsmError> {
+    let text = line.text.as_str();
+    let symbol = match text[1..].find(')') {
+        Some(right) => &text[1..right + 1],
+        None => return Err(line.error("label is missing closing ')'".to_string())),
+    };
+    let rest = &text[symbol.len() + 2..];
+    if !rest.is_empty() {
+        return Err(line.error_at(rest, "unexpected text after label".to_string()));
+    }
+    if symbol.is_empty() {
+        return Err(line.error("empty label".to_string()));
+    }
+    if is_constant(symbol) {
+        return Err(line.error_at(symbol, "label name cannot be a number".to_string()));
+    }
+    check_symbol(line, symbol)?;
+    Ok(Instruction::Label(symbol))
+}
+
+// a symbol is letters, digits, '_', '.', '$' and ':', not starting with a digit
+pub fn check_symbol(line: &Line, symbol: &str) -> Result<(), AsmError> {
+    if symbol.starts_with(|c: char| c.is_ascii_digit()) {
+        let message = format!("symbol '{}' cannot start with a digit", symbol);
+        return Err(line.error_at(symbol, message));
+    }
+    let invalid = symbol.find(|c: char| !(c.is_ascii_alphanumeric() || "_.$:".contains(c)));
+    if let Some(index) = invalid {
+        let c = symbol[index..].chars().next().unwrap();
+        let message = format!("invalid character '{}' in symbol '{}'", c, symbol);
+        return Err(line.error_at(&symbol[index..index + c.len_utf8()], message));
+    }
+    Ok(())
+}
@@ -1,7 +1,31 @@
-pub fn parse_filename(args: &[String]) -> Result<String, &'static str> {
-    if args.len() < 2 {
-        return Err("not enought argument");
-    }
-    let filename = args[1].clone();
-    return Ok(filename);
+// errors are collected and reported once per file, their size does not matter
+#![allow(clippy::result_large_err)]
+
+pub mod assembler;
+pub mod code;
+pub mod data;
+pub mod disassembler;
+pub mod error;
+pub mod expr;
+pub mod include;
+pub mod instruction;
+pub mod json;
+pub mod lsp;
+pub mod macros;
+pub mod optimize;
+pub mod output;
+pub mod parser;
+pub mod pseudo;
+pub mod symbol_table;
+
+pub use assembler::{analyze, assemble_program, Assembly, Options};
+pub use error::AsmError;
+pub use parser::Parser;
+pub use symbol_table::SymbolTable;
+
+// assemble a whole program held in memory into its ROM words
+pub fn assemble(source: &str) -> Result<Vec<u16>, Vec<AsmError>> {
+    let mut parser = Parser::from_source("<source>", source);
+    let assembly = assemble_program(&mut parser, &Options::default())?;
+    Ok(assembly.words)
 }
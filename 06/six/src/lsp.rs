@@ -0,0 +1,896 @@
+// a language server for Hack assembly, spoken over stdio by six-lsp.
+//
+// documents are assembled whenever they are opened or changed and their errors and
+// warnings published as diagnostics. go to definition, find references and hover
+// work on the symbols of a document, hover also shows the words the instruction
+// under the cursor assembles to, and completion offers the dest, comp and jump
+// mnemonics of the code tables and the symbols after '@'
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::assembler::{analyze, Options};
+use crate::error::{AsmError, Severity};
+use crate::json::{self, object, Value};
+use crate::parser::{comment_start, local_scope, read_lines, split_first_word, Parser};
+use crate::symbol_table::{SymbolKind, SymbolTable};
+use crate::{code, expr, pseudo};
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// LSP enumerations
+const FULL_SYNC: usize = 1;
+const KIND_VARIABLE: usize = 6;
+const KIND_KEYWORD: usize = 14;
+const KIND_REFERENCE: usize = 18;
+const KIND_CONSTANT: usize = 21;
+
+pub struct Server {
+    // open documents by URI
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+    // set by the exit notification, the code the process exits with
+    exit: Option<i32>,
+}
+
+// an open document and what assembling it found
+struct Document {
+    path: String,
+    lines: Vec<String>,
+    occurrences: Vec<Occurrence>,
+    symbols: SymbolTable,
+    // (line, address, word) for every ROM word of the document's own lines
+    words: Vec<(usize, usize, u16)>,
+    diagnostics: Vec<AsmError>,
+}
+
+// a symbol written in the document, line and columns as in AsmError
+struct Occurrence {
+    name: String,
+    line: usize,
+    columns: (usize, usize),
+    // a label, .equ or .data defining the symbol
+    definition: bool,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server {
+            documents: HashMap::new(),
+            shutdown: false,
+            exit: None,
+        }
+    }
+
+    // the exit code once the client has asked the server to exit
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit
+    }
+
+    // handle one message, returning the responses and notifications to send back
+    pub fn handle(&mut self, text: &str) -> Vec<Value> {
+        let message = match json::parse(text) {
+            Ok(message) => message,
+            Err(e) => return vec![error_response(Value::Null, PARSE_ERROR, e)],
+        };
+        let method = match message.get("method").as_str() {
+            Some(method) => method,
+            // a response, the server sends no requests
+            None => return Vec::new(),
+        };
+        let id = message.get("id");
+        let params = message.get("params");
+
+        if id.is_null() {
+            return self.notification(method, params);
+        }
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => self.request(params, definition),
+            "textDocument/references" => self.request(params, references),
+            "textDocument/hover" => self.request(params, hover),
+            "textDocument/completion" => self.request(params, completion),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{}'", method))),
+        };
+        match result {
+            Ok(result) => vec![object(vec![
+                ("jsonrpc", "2.0".into()),
+                ("id", id.clone()),
+                ("result", result),
+            ])],
+            Err((code, message)) => vec![error_response(id.clone(), code, message)],
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params.get("textDocument").get("uri").as_str();
+        match (method, uri) {
+            ("exit", _) => {
+                self.exit = Some(if self.shutdown { 0 } else { 1 });
+                Vec::new()
+            }
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = params.get("textDocument").get("text").as_str();
+                self.open(uri, text.unwrap_or(""))
+            }
+            // with full sync the last change holds the whole text
+            ("textDocument/didChange", Some(uri)) => {
+                let changes = params.get("contentChanges").as_array().unwrap_or(&[]);
+                match changes
+                    .last()
+                    .and_then(|change| change.get("text").as_str())
+                {
+                    Some(text) => self.open(uri, text),
+                    None => Vec::new(),
+                }
+            }
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(uri);
+                vec![publish_diagnostics(uri, Vec::new())]
+            }
+            // initialized, $/cancelRequest, ...
+            _ => Vec::new(),
+        }
+    }
+
+    fn open(&mut self, uri: &str, text: &str) -> Vec<Value> {
+        let document = Document::new(uri_to_path(uri), text);
+        let diagnostics = document
+            .diagnostics
+            .iter()
+            .map(|error| document.diagnostic(error))
+            .collect();
+        self.documents.insert(uri.to_string(), document);
+        vec![publish_diagnostics(uri, diagnostics)]
+    }
+
+    // a request about a position in an open document
+    fn request(
+        &self,
+        params: &Value,
+        answer: fn(&Document, &str, usize, usize, &Value) -> Value,
+    ) -> Result<Value, (i64, String)> {
+        let uri = params.get("textDocument").get("uri").as_str();
+        let position = params.get("position");
+        let line = position.get("line").as_usize();
+        let character = position.get("character").as_usize();
+        let (uri, line, character) = match (uri, line, character) {
+            (Some(uri), Some(line), Some(character)) => (uri, line, character),
+            _ => {
+                return Err((
+                    INVALID_PARAMS,
+                    "expected a document and a position".to_string(),
+                ))
+            }
+        };
+        match self.documents.get(uri) {
+            Some(document) => {
+                let text = document.lines.get(line).map_or("", String::as_str);
+                let column = column(text, character);
+                Ok(answer(document, uri, line + 1, column, params))
+            }
+            None => Err((INVALID_PARAMS, format!("'{}' is not open", uri))),
+        }
+    }
+}
+
+impl Default for Server {
+    fn default() -> Server {
+        Server::new()
+    }
+}
+
+fn capabilities() -> Value {
+    let triggers: Vec<Value> = ["@", "=", ";"].iter().map(|&c| c.into()).collect();
+    object(vec![
+        (
+            "capabilities",
+            object(vec![
+                ("textDocumentSync", FULL_SYNC.into()),
+                ("definitionProvider", true.into()),
+                ("referencesProvider", true.into()),
+                ("hoverProvider", true.into()),
+                (
+                    "completionProvider",
+                    object(vec![("triggerCharacters", triggers.into())]),
+                ),
+            ]),
+        ),
+        (
+            "serverInfo",
+            object(vec![
+                ("name", "six-lsp".into()),
+                ("version", env!("CARGO_PKG_VERSION").into()),
+            ]),
+        ),
+    ])
+}
+
+impl Document {
+    fn new(path: String, text: &str) -> Document {
+        let mut parser = Parser::from_source(&path, text);
+        let options = Options {
+            warn_single_use: true,
+            source_map: true,
+            ..Options::default()
+        };
+        let (assembly, diagnostics) = analyze(&mut parser, &options);
+
+        // "address<TAB>file:line..." entries of the source map
+        let words = assembly
+            .source_map
+            .iter()
+            .skip(1)
+            .filter_map(|entry| {
+                let mut fields = entry.split('\t');
+                let address: usize = fields.next()?.parse().ok()?;
+                let (file, line) = fields.next()?.rsplit_once(':')?;
+                if file != path {
+                    return None;
+                }
+                Some((line.parse().ok()?, address, assembly.words[address]))
+            })
+            .collect();
+
+        Document {
+            occurrences: occurrences(&path, text),
+            lines: text.lines().map(str::to_string).collect(),
+            path,
+            symbols: assembly.symbols,
+            words,
+            diagnostics,
+        }
+    }
+
+    fn line(&self, number: usize) -> &str {
+        self.lines
+            .get(number.wrapping_sub(1))
+            .map_or("", String::as_str)
+    }
+
+    // the LSP range of columns on a line of the document
+    fn range(&self, line: usize, columns: (usize, usize)) -> Value {
+        let text = self.line(line);
+        range(line, character(text, columns.0), character(text, columns.1))
+    }
+
+    // errors in included files are shown on the first line, naming their position
+    fn diagnostic(&self, error: &AsmError) -> Value {
+        let mut message = error.message.clone();
+        if let Some(note) = &error.note {
+            message = format!("{}\n{}", message, note);
+        }
+        let range = if error.file == self.path && error.line > 0 {
+            self.range(error.line, error.columns)
+        } else {
+            if error.line > 0 {
+                message = format!("{}:{}: {}", error.file, error.line, message);
+            } else if error.file != self.path {
+                message = format!("{}: {}", error.file, message);
+            }
+            range(1, 0, 0)
+        };
+        let severity: usize = match error.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+        };
+        object(vec![
+            ("range", range),
+            ("severity", severity.into()),
+            ("source", "six".into()),
+            ("message", message.into()),
+        ])
+    }
+
+    // the symbol under the cursor
+    fn occurrence_at(&self, line: usize, column: usize) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|occurrence| {
+            occurrence.line == line
+                && occurrence.columns.0 <= column
+                && column <= occurrence.columns.1
+        })
+    }
+
+    // the symbol table entry of a name written in the document
+    fn symbol(&self, name: &str) -> Option<&crate::symbol_table::Symbol> {
+        if name.starts_with('$') {
+            let local = format!("{}{}", local_scope(&self.path), name);
+            return self.symbols.get(&local);
+        }
+        self.symbols.get(name)
+    }
+
+    fn location(&self, uri: &str, occurrence: &Occurrence) -> Value {
+        object(vec![
+            ("uri", uri.into()),
+            ("range", self.range(occurrence.line, occurrence.columns)),
+        ])
+    }
+}
+
+// the definition of a label, constant or data name, or the first use of a variable
+fn definition(document: &Document, uri: &str, line: usize, column: usize, _: &Value) -> Value {
+    let name = match document.occurrence_at(line, column) {
+        Some(occurrence) => &occurrence.name,
+        None => return Value::Null,
+    };
+    let defined = document
+        .occurrences
+        .iter()
+        .find(|occurrence| occurrence.definition && occurrence.name == *name);
+    if let Some(occurrence) = defined {
+        return document.location(uri, occurrence);
+    }
+
+    match document.symbol(name) {
+        Some(symbol) if symbol.kind != SymbolKind::Predefined => {
+            let end = symbol.column + name.len();
+            let range = if *symbol.file == *document.path {
+                document.range(symbol.line, (symbol.column, end))
+            } else {
+                range(symbol.line, symbol.column - 1, end - 1)
+            };
+            object(vec![
+                ("uri", path_to_uri(&symbol.file).into()),
+                ("range", range),
+            ])
+        }
+        _ => Value::Null,
+    }
+}
+
+// every use of the symbol under the cursor in the document
+fn references(document: &Document, uri: &str, line: usize, column: usize, params: &Value) -> Value {
+    let name = match document.occurrence_at(line, column) {
+        Some(occurrence) => &occurrence.name,
+        None => return Value::Null,
+    };
+    let declaration = params.get("context").get("includeDeclaration") != &Value::Bool(false);
+    let locations: Vec<Value> = document
+        .occurrences
+        .iter()
+        .filter(|occurrence| occurrence.name == *name && (declaration || !occurrence.definition))
+        .map(|occurrence| document.location(uri, occurrence))
+        .collect();
+    locations.into()
+}
+
+// what the symbol under the cursor resolves to and the words of the instruction
+//
+//     LOOP  label, ROM[4]
+//
+//     0004  1110101010000111  EA87
+fn hover(document: &Document, _: &str, line: usize, column: usize, _: &Value) -> Value {
+    let mut sections: Vec<String> = Vec::new();
+    let occurrence = document.occurrence_at(line, column);
+
+    if let Some(occurrence) = occurrence {
+        let name = &occurrence.name;
+        let description = match document.symbol(name) {
+            Some(symbol) => match symbol.kind {
+                SymbolKind::Label => format!("label, ROM[{}]", symbol.address),
+                SymbolKind::Constant => format!("constant, {}", symbol.address),
+                kind => format!("{}, RAM[{}]", kind.name(), symbol.address),
+            },
+            None => "not resolved".to_string(),
+        };
+        sections.push(format!("`{}`  {}", name, description));
+    }
+
+    let words: Vec<String> = document
+        .words
+        .iter()
+        .filter(|(number, _, _)| *number == line)
+        .map(|(_, address, word)| format!("{:04}  {:016b}  {:04X}", address, word, word))
+        .collect();
+    if !words.is_empty() {
+        sections.push(format!("```\n{}\n```", words.join("\n")));
+    }
+
+    if sections.is_empty() {
+        return Value::Null;
+    }
+    let contents = object(vec![
+        ("kind", "markdown".into()),
+        ("value", sections.join("\n\n").into()),
+    ]);
+    match occurrence {
+        Some(occurrence) => object(vec![
+            ("contents", contents),
+            ("range", document.range(line, occurrence.columns)),
+        ]),
+        None => object(vec![("contents", contents)]),
+    }
+}
+
+// what may be written at the cursor: symbols after '@', jumps after ';', comps
+// after '=' and dests or comps at the start of a C-instruction
+fn completion(document: &Document, _: &str, line: usize, column: usize, _: &Value) -> Value {
+    let text = document.line(line);
+    let before = &text[..(column - 1).min(text.len())];
+    if comment_start(before) < before.len() {
+        return Value::Array(Vec::new());
+    }
+    let trimmed = before.trim_start();
+    // the part already typed is replaced by the item
+    let start = before
+        .rfind(['@', '=', ';'])
+        .map_or(before.len() - trimmed.len(), |index| index + 1);
+    let edit = document.range(line, (start + 1, column));
+
+    let mut items: Vec<(String, usize, String)> = Vec::new();
+    let mnemonics = |table: &[(&str, &str)], field: &str, suffix: &str| {
+        table
+            .iter()
+            .map(|(mnemonic, bits)| {
+                let label = format!("{}{}", mnemonic, suffix);
+                (label, KIND_KEYWORD, format!("{} {}", field, bits))
+            })
+            .collect::<Vec<_>>()
+    };
+    if trimmed.starts_with('@') {
+        items = symbol_items(document);
+    } else if before.contains(';') {
+        items.extend(mnemonics(&code::JUMP, "jump", ""));
+    } else if before.contains('=') {
+        items.extend(mnemonics(&code::COMP, "comp", ""));
+    } else if !trimmed.starts_with(['(', '.']) {
+        items.extend(mnemonics(&code::DEST, "dest", "="));
+        items.extend(mnemonics(&code::COMP, "comp", ""));
+    }
+
+    let items: Vec<Value> = items
+        .into_iter()
+        .map(|(label, kind, detail)| {
+            let text_edit = object(vec![
+                ("range", edit.clone()),
+                ("newText", label.clone().into()),
+            ]);
+            object(vec![
+                ("label", label.into()),
+                ("kind", kind.into()),
+                ("detail", detail.into()),
+                ("textEdit", text_edit),
+            ])
+        })
+        .collect();
+    items.into()
+}
+
+// the symbols of the document, then the predefined ones
+fn symbol_items(document: &Document) -> Vec<(String, usize, String)> {
+    let mut items: Vec<(String, usize, String)> = Vec::new();
+    for occurrence in &document.occurrences {
+        if items.iter().any(|(name, _, _)| *name == occurrence.name) {
+            continue;
+        }
+        let (kind, detail) = match document.symbol(&occurrence.name) {
+            Some(symbol) => {
+                let kind = match symbol.kind {
+                    SymbolKind::Label => KIND_REFERENCE,
+                    SymbolKind::Constant => KIND_CONSTANT,
+                    _ => KIND_VARIABLE,
+                };
+                (kind, format!("{} {}", symbol.kind.name(), symbol.address))
+            }
+            None => (KIND_VARIABLE, String::new()),
+        };
+        items.push((occurrence.name.clone(), kind, detail));
+    }
+    for (name, symbol) in SymbolTable::new().sorted() {
+        let detail = format!("{} {}", symbol.kind.name(), symbol.address);
+        items.push((name.clone(), KIND_VARIABLE, detail));
+    }
+    items
+}
+
+// every symbol written in the source: in A-instructions, labels, the operands of
+// .equ, .data and .word, pseudo-instructions and macro calls. %parameters of
+// macro bodies and the registers of pseudo-instructions are not symbols
+fn occurrences(path: &str, source: &str) -> Vec<Occurrence> {
+    let mut occurrences = Vec::new();
+    for line in read_lines(path, source) {
+        let text = line.text.as_str();
+        let (first, rest) = split_first_word(text);
+        let (operands, definitions) = if let Some(value) = text.strip_prefix('@') {
+            (value, 0)
+        } else if let Some(label) = text.strip_prefix('(') {
+            (label.trim_end_matches(')'), 1)
+        } else {
+            match first {
+                ".equ" | ".data" => (rest, 1),
+                ".word" => (rest, 0),
+                _ if first.starts_with('.') => continue,
+                _ if pseudo::is_pseudo(text) => (rest, 0),
+                // a C-instruction
+                _ if rest.is_empty() || text.contains(['=', ';']) => continue,
+                // a macro call
+                _ => (rest, 0),
+            }
+        };
+
+        let symbols = symbols_in(operands)
+            .into_iter()
+            .filter(|symbol| !pseudo::is_pseudo(text) || !is_register(symbol));
+        for (index, symbol) in symbols.enumerate() {
+            let (number, column) = line.position_of(symbol);
+            occurrences.push(Occurrence {
+                name: symbol.to_string(),
+                line: number,
+                columns: (column, column + symbol.len()),
+                definition: index < definitions,
+            });
+        }
+    }
+    occurrences
+}
+
+// the symbols of an expression or operand list, as slices of it
+fn symbols_in(text: &str) -> Vec<&str> {
+    let mut symbols = Vec::new();
+    let mut rest = text;
+    let mut previous = ' ';
+    while let Some(c) = rest.chars().next() {
+        if !expr::is_symbol_char(c) {
+            previous = c;
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let end = rest
+            .find(|c: char| !expr::is_symbol_char(c))
+            .unwrap_or(rest.len());
+        let word = &rest[..end];
+        if !c.is_ascii_digit() && previous != '%' {
+            symbols.push(word);
+        }
+        previous = c;
+        rest = &rest[end..];
+    }
+    symbols
+}
+
+// D, M+1, AM, ... and the RAM of RAM[x] in pseudo-instructions
+fn is_register(symbol: &str) -> bool {
+    symbol == "RAM" || code::comp(symbol).is_some() || code::dest(symbol).is_some()
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("id", id),
+        (
+            "error",
+            object(vec![
+                ("code", Value::Number(code as f64)),
+                ("message", message.into()),
+            ]),
+        ),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        (
+            "params",
+            object(vec![
+                ("uri", uri.into()),
+                ("diagnostics", diagnostics.into()),
+            ]),
+        ),
+    ])
+}
+
+// an LSP range on one line, `line` is 1-based and the characters 0-based
+fn range(line: usize, start: usize, end: usize) -> Value {
+    let position = |character: usize| {
+        object(vec![
+            ("line", line.saturating_sub(1).into()),
+            ("character", character.into()),
+        ])
+    };
+    object(vec![("start", position(start)), ("end", position(end))])
+}
+
+// LSP characters count UTF-16 code units from 0, columns count bytes from 1
+fn character(text: &str, column: usize) -> usize {
+    let end = column.saturating_sub(1).min(text.len());
+    text.get(..end).map_or(end, |s| s.encode_utf16().count())
+}
+
+fn column(text: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (index, c) in text.char_indices() {
+        if units >= character {
+            return index + 1;
+        }
+        units += c.len_utf16();
+    }
+    text.len() + 1
+}
+
+// file:///home/a%20b/Prog.asm -> /home/a b/Prog.asm
+fn uri_to_path(uri: &str) -> String {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escape = path
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn path_to_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri += &format!("%{:02X}", byte);
+        }
+    }
+    uri
+}
+
+// the body of the next message, None at the end of the input
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length: Option<usize> = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let length = match length {
+        Some(length) => length,
+        None => {
+            let message = "message without Content-Length header";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    match String::from_utf8(body) {
+        Ok(body) => Ok(Some(body)),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    }
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///tmp/test.asm";
+    const TEXT: &str = ".equ N 5\n@N\nD=A\n(LOOP)\n@i\nM=D\n@LOOP\n0;JXX\n";
+
+    // a server with TEXT open, and what opening it sent back
+    fn session() -> (Server, Vec<Value>) {
+        let mut server = Server::new();
+        let initialize = r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#;
+        let responses = server.handle(initialize);
+        assert_eq!(responses.len(), 1);
+        let capabilities = responses[0].get("result").get("capabilities");
+        assert_eq!(capabilities.get("hoverProvider"), &Value::Bool(true));
+        assert!(server
+            .handle(r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#)
+            .is_empty());
+
+        let document = object(vec![
+            ("uri", URI.into()),
+            ("languageId", "hack".into()),
+            ("version", 1.into()),
+            ("text", TEXT.into()),
+        ]);
+        let open = object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/didOpen".into()),
+            ("params", object(vec![("textDocument", document)])),
+        ]);
+        let notifications = server.handle(&open.to_string());
+        (server, notifications)
+    }
+
+    // the result of `method` at line and character, both 0-based
+    fn request(server: &mut Server, method: &str, line: usize, character: usize) -> Value {
+        let position = object(vec![("line", line.into()), ("character", character.into())]);
+        let params = object(vec![
+            ("textDocument", object(vec![("uri", URI.into())])),
+            ("position", position),
+            ("context", object(vec![("includeDeclaration", true.into())])),
+        ]);
+        let message = object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", 2.into()),
+            ("method", method.into()),
+            ("params", params),
+        ]);
+        let mut responses = server.handle(&message.to_string());
+        assert_eq!(responses.len(), 1);
+        let response = responses.remove(0);
+        assert_eq!(response.get("id").as_usize(), Some(2));
+        response.get("result").clone()
+    }
+
+    // (line, start character) of a range
+    fn start(range: &Value) -> (usize, usize) {
+        let start = range.get("start");
+        (
+            start.get("line").as_usize().unwrap(),
+            start.get("character").as_usize().unwrap(),
+        )
+    }
+
+    // the labels of completion items
+    fn labels(items: &Value) -> Vec<&str> {
+        items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item.get("label").as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn diagnostics() {
+        let (_, notifications) = session();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(
+            notifications[0].get("method").as_str(),
+            Some("textDocument/publishDiagnostics")
+        );
+        let params = notifications[0].get("params");
+        assert_eq!(params.get("uri").as_str(), Some(URI));
+        let diagnostics = params.get("diagnostics").as_array().unwrap();
+        assert_eq!(diagnostics.len(), 2);
+        let warning = &diagnostics[0];
+        assert_eq!(
+            warning.get("message").as_str(),
+            Some("variable 'i' is only referenced once")
+        );
+        assert_eq!(warning.get("severity").as_usize(), Some(2));
+        assert_eq!(start(warning.get("range")), (4, 1));
+        let error = &diagnostics[1];
+        assert_eq!(
+            error.get("message").as_str(),
+            Some("unknown jump mnemonic 'JXX'")
+        );
+        assert_eq!(error.get("severity").as_usize(), Some(1));
+        assert_eq!(start(error.get("range")), (7, 2));
+    }
+
+    #[test]
+    fn half_typed_line_keeps_other_diagnostics() {
+        let (mut server, _) = session();
+        let text = format!("push\n{}", TEXT);
+        let change = object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/didChange".into()),
+            (
+                "params",
+                object(vec![
+                    ("textDocument", object(vec![("uri", URI.into())])),
+                    (
+                        "contentChanges",
+                        vec![object(vec![("text", text.into())])].into(),
+                    ),
+                ]),
+            ),
+        ]);
+        let notifications = server.handle(&change.to_string());
+        let diagnostics = notifications[0].get("params").get("diagnostics");
+        assert_eq!(diagnostics.as_array().map(<[Value]>::len), Some(3));
+        // the symbols of the other lines are still known
+        let hover = request(&mut server, "textDocument/hover", 2, 1);
+        assert!(!hover.is_null());
+    }
+
+    #[test]
+    fn definition_and_references() {
+        let (mut server, _) = session();
+        let definition = request(&mut server, "textDocument/definition", 6, 2);
+        assert_eq!(definition.get("uri").as_str(), Some(URI));
+        assert_eq!(start(definition.get("range")), (3, 1));
+
+        let references = request(&mut server, "textDocument/references", 3, 2);
+        let starts: Vec<(usize, usize)> = references
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| start(location.get("range")))
+            .collect();
+        assert_eq!(starts, [(3, 1), (6, 1)]);
+
+        // nothing under the cursor
+        assert!(request(&mut server, "textDocument/definition", 2, 0).is_null());
+    }
+
+    #[test]
+    fn hover() {
+        let (mut server, _) = session();
+        let hover = request(&mut server, "textDocument/hover", 1, 1);
+        let contents = hover.get("contents").get("value").as_str().unwrap();
+        assert!(contents.starts_with("`N`  constant, 5"));
+        assert!(contents.contains("0000  0000000000000101  0005"));
+        assert_eq!(start(hover.get("range")), (1, 1));
+    }
+
+    #[test]
+    fn completion() {
+        let (mut server, _) = session();
+        let symbols = request(&mut server, "textDocument/completion", 6, 1);
+        let symbols = labels(&symbols);
+        assert_eq!(symbols[..3], ["N", "LOOP", "i"]);
+        assert!(symbols.contains(&"SCREEN"));
+
+        let jumps = request(&mut server, "textDocument/completion", 7, 2);
+        assert_eq!(
+            labels(&jumps),
+            ["JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"]
+        );
+    }
+
+    #[test]
+    fn errors_and_shutdown() {
+        let (mut server, _) = session();
+        let responses = server.handle("{");
+        let code = responses[0].get("error").get("code");
+        assert_eq!(code, &Value::Number(PARSE_ERROR as f64));
+
+        let unknown = r#"{"jsonrpc":"2.0","id":3,"method":"foo","params":{}}"#;
+        let responses = server.handle(unknown);
+        assert_eq!(
+            responses[0].get("error").get("message").as_str(),
+            Some("unknown method 'foo'")
+        );
+
+        let close = format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/didClose","params":{{"textDocument":{{"uri":"{}"}}}}}}"#,
+            URI
+        );
+        let notifications = server.handle(&close);
+        let diagnostics = notifications[0].get("params").get("diagnostics");
+        assert_eq!(diagnostics.as_array().map(<[Value]>::len), Some(0));
+
+        assert_eq!(server.exit_code(), None);
+        server.handle(r#"{"jsonrpc":"2.0","id":4,"method":"shutdown"}"#);
+        server.handle(r#"{"jsonrpc":"2.0","method":"exit"}"#);
+        assert_eq!(server.exit_code(), Some(0));
+    }
+}
@@ -0,0 +1,343 @@
+// .macro NAME arg, ... / .endm definitions and their expansion.
+//
+//     .macro POPD
+//     @SP
+//     AM=M-1
+//     D=M
+//     .endm
+//
+//     .macro LOADC value, dest
+//     @%value
+//     D=A
+//     @%dest
+//     M=D
+//     .endm
+//
+// parameters are referenced as %name in the body. labels defined in a body are
+// local to each expansion, so a macro with a loop can be used more than once.
+use std::collections::HashMap;
+
+use crate::error::AsmError;
+use crate::parser::{split_first_word, Expansion, Line, Source};
+use crate::{code, expr, pseudo};
+
+// calls nested deeper than this are taken as recursion
+const MAX_DEPTH: usize = 64;
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Line>,
+    // labels defined in the body
+    labels: Vec<String>,
+}
+
+struct Expander {
+    macros: HashMap<String, Macro>,
+    // macros whose definition has errors, their calls are dropped without more errors
+    broken: Vec<String>,
+    // counts expansions to give local labels unique names
+    count: usize,
+    errors: Vec<AsmError>,
+}
+
+// definitions and calls that fail are dropped, the other lines are kept
+pub fn expand(code: Vec<Line>) -> (Vec<Line>, Vec<AsmError>) {
+    let mut expander = Expander {
+        macros: HashMap::new(),
+        broken: Vec::new(),
+        count: 0,
+        errors: Vec::new(),
+    };
+
+    let mut output = Vec::new();
+    let mut lines = code.into_iter();
+    while let Some(line) = lines.next() {
+        let (directive, rest) = split_first_word(&line.text);
+        match directive {
+            ".macro" => {
+                let rest = rest.to_string();
+                expander.define(line, &rest, &mut lines);
+            }
+            ".endm" => {
+                let message = "'.endm' without '.macro'".to_string();
+                expander.errors.push(line.error_at(&line.text, message));
+            }
+            _ if expander.macros.contains_key(directive) => {
+                expander.call(&line, None, 0, &mut output);
+            }
+            _ if expander.broken.iter().any(|name| name == directive) => (),
+            _ => output.push(line),
+        }
+    }
+
+    (output, expander.errors)
+}
+
+impl Expander {
+    // read a definition up to its .endm
+    fn define(&mut self, header: Line, rest: &str, lines: &mut impl Iterator<Item = Line>) {
+        let (name, params) = split_first_word(rest);
+        let params: Vec<String> = split_arguments(params);
+
+        let mut body = Vec::new();
+        let mut closed = false;
+        for line in lines.by_ref() {
+            match split_first_word(&line.text).0 {
+                ".endm" => {
+                    closed = true;
+                    break;
+                }
+                ".macro" => {
+                    let message = "macro definitions cannot be nested".to_string();
+                    self.errors.push(line.error_at(&line.text, message));
+                }
+                _ => body.push(line),
+            }
+        }
+
+        let error = |message: String| header.error_at(&header.text, message);
+        if !closed {
+            self.errors
+                .push(error(format!("macro '{}' is missing '.endm'", name)));
+            return;
+        }
+        if name.is_empty() || !expr::is_symbol(name) {
+            self.errors
+                .push(error(format!("invalid macro name '{}'", name)));
+            return;
+        }
+        if code::comp(name).is_some() {
+            let message = format!("macro name '{}' is also an instruction", name);
+            self.errors.push(error(message));
+            return;
+        }
+        if self.macros.contains_key(name) {
+            self.errors
+                .push(error(format!("macro '{}' is already defined", name)));
+            return;
+        }
+        if let Some(param) = params.iter().find(|param| !expr::is_symbol(param)) {
+            let message = format!("invalid parameter name '{}'", param);
+            self.errors.push(error(message));
+            self.broken.push(name.to_string());
+            return;
+        }
+        let mut unknown = false;
+        for line in &body {
+            for reference in parameters(&line.text) {
+                if !params.iter().any(|param| param == reference) {
+                    let message = format!("macro '{}' has no parameter '%{}'", name, reference);
+                    self.errors.push(line.error_at(reference, message));
+                    unknown = true;
+                }
+            }
+        }
+        if unknown {
+            self.broken.push(name.to_string());
+            return;
+        }
+
+        let labels = body
+            .iter()
+            .filter_map(|line| line.text.strip_prefix('('))
+            .filter_map(|label| label.strip_suffix(')'))
+            .map(|label| label.to_string())
+            .collect();
+
+        let definition = Macro {
+            params,
+            body,
+            labels,
+        };
+        self.macros.insert(name.to_string(), definition);
+    }
+
+    // expand the call on `line` into `output`. `outer` is set when the call itself
+    // comes from another macro's body
+    fn call(
+        &mut self,
+        line: &Line,
+        outer: Option<&Expansion>,
+        depth: usize,
+        output: &mut Vec<Line>,
+    ) {
+        let (name, args) = split_first_word(&line.text);
+        let args = split_arguments(args);
+
+        if depth >= MAX_DEPTH {
+            let message = format!("macro '{}' expands recursively", name);
+            self.errors.push(line.error_at(&line.text, message));
+            return;
+        }
+
+        let params = self.macros[name].params.len();
+        if args.len() != params {
+            let message = format!(
+                "macro '{}' takes {} argument(s) but {} were given",
+                name,
+                params,
+                args.len()
+            );
+            self.errors.push(line.error_at(&line.text, message));
+            return;
+        }
+
+        self.count += 1;
+        let definition = &self.macros[name];
+        // lines carry the macro whose body they come from and the call in the file
+        let expansion = Expansion {
+            name: name.to_string(),
+            file: outer.map_or(line.file.clone(), |outer| outer.file.clone()),
+            line: outer.map_or(line.number, |outer| outer.line),
+        };
+
+        let mut expanded = Vec::new();
+        for body_line in &definition.body {
+            // labels first, so an argument naming a label of the caller is kept
+            let text = rename_labels(&body_line.text, &definition.labels, name, self.count);
+            let text = substitute(&text, &definition.params, &args);
+
+            let changed = text != body_line.text;
+            expanded.push(Line {
+                source: if changed {
+                    Source::new(&text)
+                } else {
+                    body_line.source.clone()
+                },
+                column: if changed { 1 } else { body_line.column },
+                text,
+                file: body_line.file.clone(),
+                number: body_line.number,
+                expansion: Some(expansion.clone()),
+                vm: line.vm.clone(),
+                scopes: Vec::new(),
+            });
+        }
+
+        for line in expanded {
+            if self.macros.contains_key(split_first_word(&line.text).0) {
+                self.call(&line, Some(&expansion), depth + 1, output);
+            } else {
+                output.push(line);
+            }
+        }
+    }
+}
+
+// the names of the %name references in `text`, as slices of it. a name is read
+// whole, so %ab never refers to a parameter a
+fn parameters(text: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = text;
+    while let Some(index) = rest.find('%') {
+        let after = &rest[index + 1..];
+        let end = after
+            .find(|c: char| !expr::is_symbol_char(c))
+            .unwrap_or(after.len());
+        names.push(&after[..end]);
+        rest = &after[end..];
+    }
+    names
+}
+
+// replace each %param of `text` by its argument
+fn substitute(text: &str, params: &[String], args: &[String]) -> String {
+    let mut output = String::new();
+    let mut last = 0;
+    for name in parameters(text) {
+        let start = name.as_ptr() as usize - text.as_ptr() as usize;
+        output += &text[last..start - 1];
+        match params.iter().position(|param| param == name) {
+            Some(index) => output += &args[index],
+            None => output += &text[start - 1..start + name.len()],
+        }
+        last = start + name.len();
+    }
+    output + &text[last..]
+}
+
+// LOOP in the 3rd expansion of MUL becomes MUL.3.LOOP. %LOOP is a parameter
+// and stays as it is
+fn rename_labels(text: &str, labels: &[String], name: &str, count: usize) -> String {
+    if !(text.starts_with('@') || text.starts_with('(') || pseudo::is_pseudo(text)) {
+        return text.to_string();
+    }
+    let local = |symbol: &str| {
+        if labels.iter().any(|label| label == symbol) {
+            Some(format!("{}.{}.{}", name, count, symbol))
+        } else {
+            None
+        }
+    };
+    let mut output = String::new();
+    let mut last = 0;
+    for reference in parameters(text) {
+        let start = reference.as_ptr() as usize - text.as_ptr() as usize;
+        output += &expr::rename_symbols(&text[last..start], &local);
+        output += reference;
+        last = start + reference.len();
+    }
+    output + &expr::rename_symbols(&text[last..], &local)
+}
+
+fn split_arguments(text: &str) -> Vec<String> {
+    if text.is_empty() {
+        return Vec::new();
+    }
+    text.split(',').map(|arg| arg.trim().to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::read_lines;
+
+    fn expanded(source: &str) -> Vec<String> {
+        let (lines, errors) = expand(read_lines("test.asm", source));
+        if let Some(error) = errors.first() {
+            panic!("{}", error.message);
+        }
+        lines.into_iter().map(|line| line.text).collect()
+    }
+
+    #[test]
+    fn parameter_prefix_of_another() {
+        let source = ".macro SET a, ab\n@%ab\nD=A\n@%a\nM=D\n.endm\nSET x, 5\n";
+        assert_eq!(expanded(source), ["@5", "D=A", "@x", "M=D"]);
+    }
+
+    #[test]
+    fn unknown_parameter() {
+        // the call of the broken macro is dropped, the lines around it are kept
+        let source = ".macro SET a\n@%b\n.endm\nD=A\nSET 1\nM=D\n";
+        let (lines, errors) = expand(read_lines("test.asm", source));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("'%b'"));
+        let lines: Vec<String> = lines.into_iter().map(|line| line.text).collect();
+        assert_eq!(lines, ["D=A", "M=D"]);
+    }
+
+    #[test]
+    fn labels_local_to_each_expansion() {
+        let source = ".macro WAIT\n(LOOP)\n@LOOP\n0;JMP\n.endm\nWAIT\nWAIT\n@LOOP\n";
+        let lines = expanded(source);
+        assert_eq!(
+            lines,
+            [
+                "(WAIT.1.LOOP)",
+                "@WAIT.1.LOOP",
+                "0;JMP",
+                "(WAIT.2.LOOP)",
+                "@WAIT.2.LOOP",
+                "0;JMP",
+                "@LOOP",
+            ]
+        );
+    }
+
+    #[test]
+    fn arguments_not_renamed_as_labels() {
+        let source = ".macro GO dest\n(LOOP)\n@%dest\n.endm\nGO LOOP\n";
+        assert_eq!(expanded(source), ["(GO.1.LOOP)", "@LOOP"]);
+    }
+}
@@ -1,382 +1,374 @@
-// use std::any::type_name;
-use std::collections::HashMap;
+// see lib.rs
+#![allow(clippy::result_large_err)]
+
 use std::env;
-use std::fs;
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::BufReader;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::process;
 
+use six::disassembler::{self, SymbolMap};
+use six::error::{self, AsmError};
+use six::output::{Format, FORMATS};
+use six::{assemble_program, Options, Parser};
+
+const USAGE: &str = "\
+usage: six [-o output.hack] [-f format] [-O] [-W] [-l] [-s] [--symbols-json] [--source-map] [--link] <file.asm | directory>...
+       six -d [-o output.asm] [-m file.sym] <file.hack | directory>...";
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    let mut parser = match Parser::new(&args) {
-        Ok(a) => a,
+    let config = match Config::new(&args) {
+        Ok(config) => config,
         Err(e) => {
-            print!("{}", e);
+            eprintln!("{}\n{}", e, USAGE);
             process::exit(1);
         }
     };
 
-    let mut symboltable = SymbolTable::new();
-    let mut addresses: Vec<String> = Vec::new();
-
-    // first loop to collect symbol (Xxx)
-    let mut address_num: usize = 0;
-    while parser.has_more_commands() {
-        match parser.command_type() {
-            Some(CommandType::LCOMMAND) => {
-                let symbol = match parser.symbol() {
-                    Some(sym) => sym,
-                    None => panic!(),
-                };
-                symboltable.add_entry(symbol.to_string(), address_num);
-            }
-            _ => address_num += 1,
+    let extension = if config.disassemble { "hack" } else { "asm" };
+    let sources = match collect_sources(&config.inputs, extension) {
+        Ok(sources) => sources,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
         }
-        parser.advance();
+    };
+
+    if config.output.is_some() && sources.len() > 1 && !config.link {
+        eprintln!("-o can only be used with a single input file\n{}", USAGE);
+        process::exit(1);
     }
 
-    parser.reset();
-
-    // second loop
-    address_num = 16;
-    while parser.has_more_commands() {
-        let mut binary_code = "".to_string();
-
-        match parser.command_type() {
-            Some(CommandType::ACOMMAND) => {
-                let symbol = match parser.symbol() {
-                    Some(sym) => sym,
-                    None => panic!(),
-                };
-                if symbol.parse::<usize>().is_ok() {
-                    binary_code =
-                        format!("{:0>16}", format!("{:b}", symbol.parse::<usize>().unwrap()));
-                } else {
-                    if !symboltable.contains(&symbol.to_string()) {
-                        symboltable.add_entry(symbol.to_string(), address_num);
-                        address_num += 1;
-                    };
-                    let address = symboltable.get_address(symbol).unwrap();
-                    binary_code = format!("{:0>16}", format!("{:b}", address))
-                }
-            }
-            Some(CommandType::CCOMMAND) => {
-                let comp = parser.comp().unwrap_or("null");
-                let dest = parser.dest().unwrap_or("null");
-                let jump = parser.jump().unwrap_or("null");
-                let comp = code::comp(comp);
-                let dest = code::dest(dest);
-                let jump = code::jump(jump);
-                binary_code = format!("111{}{}{}", comp, dest, jump);
+    if config.link {
+        let output = match &config.output {
+            Some(output) => output.clone(),
+            None => get_linked_filename(&config.inputs, &sources, config.format),
+        };
+        match assemble_files(&sources, &output, &config) {
+            Ok(warnings) => error::report(&warnings),
+            Err(errors) => {
+                error::report(&errors);
+                process::exit(1);
             }
-            _ => (),
         }
+        return;
+    }
 
-        if binary_code.len() != 0 {
-            addresses.push(binary_code);
+    let mut failed = false;
+    for source in &sources {
+        let output = match &config.output {
+            Some(output) => output.clone(),
+            None if config.disassemble => source.with_extension("dis.asm"),
+            None => get_output_filename(source, config.format),
+        };
+        let result = if config.disassemble {
+            disassemble_file(source, &output, &config)
+        } else {
+            assemble_files(std::slice::from_ref(source), &output, &config)
+        };
+        match result {
+            Ok(warnings) => error::report(&warnings),
+            Err(errors) => {
+                error::report(&errors);
+                failed = true;
+            }
         }
-
-        parser.advance();
     }
 
-    let address = addresses.join("\n");
-    fs::write(format!("{}.hack", "test"), address).unwrap();
-}
-
-fn parse_filename(args: &[String]) -> Result<String, &'static str> {
-    if args.len() < 2 {
-        return Err("not enought argument");
+    if failed {
+        process::exit(1);
     }
-    let filename = args[1].clone();
-    return Ok(filename);
-}
-
-struct Parser {
-    code: Vec<String>,
-    position: usize,
 }
 
-#[derive(PartialEq)]
-enum CommandType {
-    ACOMMAND,
-    CCOMMAND,
-    LCOMMAND,
-}
-
-impl Parser {
-    pub fn new(args: &[String]) -> Result<Parser, &'static str> {
-        let filename = parse_filename(args);
-        let filename = filename?;
-        let f = match File::open(filename) {
-            Ok(f) => f,
-            Err(_) => return Err("cannot open file"),
-        };
-
-        let reader = BufReader::new(f);
-        let mut buf = Vec::new();
-
-        for line in reader.lines() {
-            let line = match line {
-                Ok(s) => s,
-                Err(_) => continue,
-            };
-
-            let right = match line.find("//") {
-                Some(right) => right,
-                None => line.len(),
-            };
-
-            let line = line[0..right].trim();
-
-            if line.len() == 0 {
-                continue;
-            }
-            buf.push(String::from(line));
+// assemble the inputs as one program. on success returns the warnings,
+// on failure every error and warning
+fn assemble_files(
+    inputs: &[PathBuf],
+    output: &Path,
+    config: &Config,
+) -> Result<Vec<AsmError>, Vec<AsmError>> {
+    let mut parsers = Vec::new();
+    for input in inputs {
+        match Parser::new(input) {
+            Ok(parser) => parsers.push(parser),
+            Err(e) => return Err(vec![e]),
         }
-
-        Ok(Parser {
-            code: buf,
-            position: 0,
-        })
     }
-
-    fn reset(&mut self) {
-        self.position = 0;
+    let mut parser = parsers.remove(0);
+    for other in parsers {
+        parser.append(other);
     }
 
-    fn print(&self) {
-        // print code for debug;
-        println!("code: {:?}", self.code[self.position]);
-        match self.dest() {
-            None => println!("dest none"),
-            Some(s) => {
-                println!("dest = {:?}", s);
-            }
-        };
-
-        match self.comp() {
-            None => println!("comp none"),
-            Some(s) => {
-                println!("comp = {:?}", s);
-            }
-        };
+    let options = Options {
+        warn_single_use: config.warn_single_use,
+        listing: config.listing,
+        optimize: config.optimize,
+        source_map: config.source_map,
+    };
+    let assembly = assemble_program(&mut parser, &options)?;
 
-        match self.jump() {
-            None => println!("jump none"),
-            Some(s) => {
-                println!("jump = {:?}", s);
-            }
-        }
+    for (file, count) in &assembly.saved {
+        println!("{}: {} instruction(s) saved", file, count);
     }
+    println!("{}: {}", parser.filename, assembly.usage());
 
-    fn advance(&mut self) {
-        if self.has_more_commands() {
-            self.position += 1;
-        };
+    if let Err(e) = write_image(output, config.format, &assembly.words, &parser.filename) {
+        return Err(vec![e]);
     }
 
-    fn has_more_commands(&self) -> bool {
-        if self.position < self.code.len() {
-            true
-        } else {
-            false
+    // the RAM image uses the same format as the ROM, Foo.hack -> Foo.ram.hack
+    if !assembly.ram.is_empty() {
+        let path = output.with_extension(format!("ram.{}", config.format.extension()));
+        if let Err(e) = write_image(&path, config.format, &assembly.ram, &parser.filename) {
+            return Err(vec![e]);
         }
     }
 
-    fn command_type(&self) -> Option<CommandType> {
-        if !self.has_more_commands() {
-            return None;
+    if config.listing {
+        let listing = assembly.listing.join("\n") + "\n";
+        if let Err(e) = write_output(&output.with_extension("lst"), &listing, &parser.filename) {
+            return Err(vec![e]);
         }
+    }
 
-        if self.code[self.position].starts_with("@") {
-            return Some(CommandType::ACOMMAND);
-        } else if self.code[self.position].starts_with("(") {
-            return Some(CommandType::LCOMMAND);
-        } else {
-            return Some(CommandType::CCOMMAND);
+    if config.source_map {
+        let map = assembly.source_map.join("\n") + "\n";
+        if let Err(e) = write_output(&output.with_extension("map"), &map, &parser.filename) {
+            return Err(vec![e]);
         }
     }
 
-    fn symbol(&self) -> Option<&str> {
-        let res = match self.command_type() {
-            None => return None,
-            Some(CommandType::CCOMMAND) => return None,
-            Some(CommandType::ACOMMAND) => &self.code[self.position],
-            Some(CommandType::LCOMMAND) => &self.code[self.position],
-        };
-
-        if res.starts_with("@") {
-            return Some(&res[1..]);
+    if config.symbols {
+        let symbols = assembly.symbols.to_text();
+        if let Err(e) = write_output(&output.with_extension("sym"), &symbols, &parser.filename) {
+            return Err(vec![e]);
         }
+    }
 
-        if res.starts_with("(") {
-            if !res.ends_with(")") {
-                print!("symbol input format is incorrect");
-                return None;
-            }
-            return Some(&res[1..res.len() - 1]);
+    if config.symbols_json {
+        let symbols = assembly.symbols.to_json();
+        let path = output.with_extension("sym.json");
+        if let Err(e) = write_output(&path, &symbols, &parser.filename) {
+            return Err(vec![e]);
         }
-
-        print!("symbol input format is incorrect");
-        None
     }
 
-    fn dest(&self) -> Option<&str> {
-        let code = match self.command_type() {
-            Some(CommandType::CCOMMAND) => &self.code[self.position],
-            _ => return None,
-        };
-
-        let right: usize = code.find("=")?;
-        let res = &self.code[self.position][0..right];
-        Some(res)
-    }
+    Ok(assembly.warnings)
+}
 
-    fn comp(&self) -> Option<&str> {
-        let code = match self.command_type() {
-            Some(CommandType::CCOMMAND) => &self.code[self.position],
-            _ => return None,
-        };
+// Foo.hack -> Foo.dis.asm, naming addresses from the -m symbol map if given
+fn disassemble_file(
+    input: &Path,
+    output: &Path,
+    config: &Config,
+) -> Result<Vec<AsmError>, Vec<AsmError>> {
+    let filename = input.display().to_string();
+    let source = match fs::read_to_string(input) {
+        Ok(source) => source,
+        Err(e) => {
+            let message = format!("cannot open file: {}", e);
+            return Err(vec![AsmError::new(&filename, message)]);
+        }
+    };
 
-        let left: usize = match code.find("=") {
-            None => 0,
-            Some(num) => num + 1,
-        };
-        let right: usize = code.find(";").unwrap_or(code.len());
+    let symbols = match &config.symbol_map {
+        Some(path) => match SymbolMap::load(path) {
+            Ok(symbols) => symbols,
+            Err(e) => return Err(vec![e]),
+        },
+        None => SymbolMap::default(),
+    };
 
-        Some(&self.code[self.position][left..right])
+    let (assembly, warnings) = disassembler::disassemble(&source, &filename, &symbols)?;
+    if let Err(e) = write_output(output, &assembly, &filename) {
+        return Err(vec![e]);
     }
+    Ok(warnings)
+}
 
-    fn jump(&self) -> Option<&str> {
-        let code = match self.command_type() {
-            Some(CommandType::CCOMMAND) => &self.code[self.position],
-            _ => return None,
-        };
-
-        let left: usize = code.find(";")? + 1;
-
-        Some(&self.code[self.position][left..])
+fn write_output<C: AsRef<[u8]>>(path: &Path, contents: C, filename: &str) -> Result<(), AsmError> {
+    match fs::write(path, contents) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let message = format!("cannot write {}: {}", path.display(), e);
+            Err(AsmError::new(filename, message))
+        }
     }
 }
-
-struct SymbolTable {
-    table: HashMap<String, usize>,
+// stream the encoded words to `path`
+fn write_image(path: &Path, format: Format, words: &[u16], filename: &str) -> Result<(), AsmError> {
+    let result = File::create(path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        format.write(words, &mut writer)?;
+        writer.flush()
+    });
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let message = format!("cannot write {}: {}", path.display(), e);
+            Err(AsmError::new(filename, message))
+        }
+    }
 }
 
-impl SymbolTable {
-    pub fn new() -> SymbolTable {
-        let mut new_table = SymbolTable {
-            table: HashMap::new(),
-        };
-
-        new_table.table.insert("SP".to_string(), 0);
-        new_table.table.insert("LCL".to_string(), 1);
-        new_table.table.insert("ARG".to_string(), 2);
-        new_table.table.insert("THIS".to_string(), 3);
-        new_table.table.insert("THAT".to_string(), 4);
-        new_table.table.insert("R0".to_string(), 0);
-        new_table.table.insert("R1".to_string(), 1);
-        new_table.table.insert("R2".to_string(), 2);
-        new_table.table.insert("R3".to_string(), 3);
-        new_table.table.insert("R4".to_string(), 4);
-        new_table.table.insert("R5".to_string(), 5);
-        new_table.table.insert("R6".to_string(), 6);
-        new_table.table.insert("R7".to_string(), 7);
-        new_table.table.insert("R8".to_string(), 8);
-        new_table.table.insert("R9".to_string(), 9);
-        new_table.table.insert("R10".to_string(), 10);
-        new_table.table.insert("R11".to_string(), 11);
-        new_table.table.insert("R12".to_string(), 12);
-        new_table.table.insert("R13".to_string(), 13);
-        new_table.table.insert("R14".to_string(), 14);
-        new_table.table.insert("R15".to_string(), 15);
-        new_table.table.insert("SCREEN".to_string(), 16384);
-        new_table.table.insert("KBD".to_string(), 24576);
-
-        new_table
-    }
+struct Config {
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    // warn about variables referenced only once, usually a typo
+    warn_single_use: bool,
+    // also write Foo.lst with address, binary and source of every instruction
+    listing: bool,
+    // also write the symbol table to Foo.sym and/or Foo.sym.json
+    symbols: bool,
+    symbols_json: bool,
+    // turn .hack files back into assembly instead
+    disassemble: bool,
+    // .sym file whose names the disassembler puts back
+    symbol_map: Option<PathBuf>,
+    // encoding of the assembled ROM image
+    format: Format,
+    // assemble all inputs into a single ROM image
+    link: bool,
+    // run the peephole optimiser and report what it saved
+    optimize: bool,
+    // also write Foo.map from ROM addresses to .asm lines and VM commands
+    source_map: bool,
+}
 
-    fn add_entry(&mut self, symbol: String, address: usize) {
-        if self.contains(&symbol) {
-            return;
-        } else {
-            self.table.insert(symbol, address);
+impl Config {
+    fn new(args: &[String]) -> Result<Config, String> {
+        let mut inputs = Vec::new();
+        let mut output = None;
+        let mut warn_single_use = false;
+        let mut listing = false;
+        let mut symbols = false;
+        let mut symbols_json = false;
+        let mut disassemble = false;
+        let mut symbol_map = None;
+        let mut format = Format::Hack;
+        let mut link = false;
+        let mut optimize = false;
+        let mut source_map = false;
+
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" => match args.next() {
+                    Some(path) => output = Some(PathBuf::from(path)),
+                    None => return Err("-o requires an output path".to_string()),
+                },
+                "-W" | "--warn-single-use" => warn_single_use = true,
+                "-l" | "--listing" => listing = true,
+                "-s" | "--symbols" => symbols = true,
+                "--symbols-json" => symbols_json = true,
+                "-d" | "--disassemble" => disassemble = true,
+                "--link" => link = true,
+                "-O" | "--optimize" => optimize = true,
+                "--source-map" => source_map = true,
+                "-f" | "--format" => {
+                    let name = match args.next() {
+                        Some(name) => name,
+                        None => return Err("-f requires a format".to_string()),
+                    };
+                    format = match Format::from_name(name) {
+                        Some(format) => format,
+                        None => {
+                            let names: Vec<&str> = FORMATS.iter().map(|(name, _)| *name).collect();
+                            return Err(format!(
+                                "unknown format {}, expected one of {}",
+                                name,
+                                names.join(", ")
+                            ));
+                        }
+                    };
+                }
+                "-m" | "--symbol-map" => match args.next() {
+                    Some(path) => symbol_map = Some(PathBuf::from(path)),
+                    None => return Err("-m requires a .sym file".to_string()),
+                },
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+                _ => inputs.push(PathBuf::from(arg)),
+            }
         }
-    }
 
-    fn contains(&self, symbol: &str) -> bool {
-        match self.table.get(symbol) {
-            None => false,
-            _ => true,
+        if inputs.is_empty() {
+            return Err("not enought argument".to_string());
+        }
+        if disassemble && link {
+            return Err("--link cannot be used with -d, only assembly is linked".to_string());
         }
-    }
 
-    fn get_address(&self, symbol: &str) -> Option<&usize> {
-        self.table.get(symbol)
+        Ok(Config {
+            inputs,
+            output,
+            warn_single_use,
+            listing,
+            symbols,
+            symbols_json,
+            disassemble,
+            symbol_map,
+            format,
+            link,
+            optimize,
+            source_map,
+        })
     }
 }
 
-pub mod code {
-    // code modele
-    pub fn dest(mnemonic: &str) -> &str {
-        match mnemonic {
-            "M" => "001",
-            "D" => "010",
-            "MD" => "011",
-            "A" => "100",
-            "AM" => "101",
-            "AD" => "110",
-            "AMD" => "111",
-            _ => "000",
-        }
-    }
+// expand directories into the .asm (or .hack) files they contain, sorted so the
+// order does not depend on the filesystem
+fn collect_sources(inputs: &[PathBuf], extension: &str) -> Result<Vec<PathBuf>, String> {
+    let mut sources = Vec::new();
 
-    pub fn comp(mnemonic: &str) -> &str {
-        match mnemonic {
-            "0" => "0101010",
-            "1" => "0111111",
-            "-1" => "0111010",
-            "D" => "0001100",
-            "A" => "0110000",
-            "!D" => "0001101",
-            "!A" => "0110001",
-            "-D" => "0001111",
-            "-A" => "0110011",
-            "D+1" => "0011111",
-            "A+1" => "0110111",
-            "D-1" => "0001110",
-            "A-1" => "0110010",
-            "D+A" => "0000010",
-            "D-A" => "0010011",
-            "A-D" => "0000111",
-            "D&A" => "0000000",
-            "D|A" => "0010101",
-            "M" => "1110000",
-            "!M" => "1110001",
-            "-M" => "1110011",
-            "M+1" => "1110111",
-            "M-1" => "1110010",
-            "D+M" => "1000010",
-            "D-M" => "1010011",
-            "M-D" => "1000111",
-            "D&M" => "1000000",
-            "D|M" => "1010101",
-            _ => panic!("unexpected mnemonic was passed {}", mnemonic),
+    for input in inputs {
+        if input.is_dir() {
+            let entries = match input.read_dir() {
+                Ok(entries) => entries,
+                Err(e) => return Err(format!("cannot read {}: {}", input.display(), e)),
+            };
+            let mut files = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.is_file() && path.extension().is_some_and(|ext| ext == extension)
+                })
+                .collect::<Vec<PathBuf>>();
+            if files.is_empty() {
+                return Err(format!("no .{} file in {}", extension, input.display()));
+            }
+            files.sort();
+            sources.append(&mut files);
+        } else {
+            sources.push(input.clone());
         }
     }
 
-    pub fn jump(mnemonic: &str) -> &str {
-        match mnemonic {
-            "JGT" => "001",
-            "JEQ" => "010",
-            "JGE" => "011",
-            "JLT" => "100",
-            "JNE" => "101",
-            "JLE" => "110",
-            "JMP" => "111",
-            _ => "000",
+    Ok(sources)
+}
+
+// linking the directory Prog gives Prog/Prog.hack, linking files the name of the first one
+fn get_linked_filename(inputs: &[PathBuf], sources: &[PathBuf], format: Format) -> PathBuf {
+    match inputs {
+        [dir] if dir.is_dir() => {
+            let name = dir
+                .canonicalize()
+                .ok()
+                .and_then(|dir| dir.file_name().map(|name| name.to_owned()));
+            match name {
+                Some(name) => dir.join(name).with_extension(format.extension()),
+                None => get_output_filename(&sources[0], format),
+            }
         }
+        _ => get_output_filename(&sources[0], format),
     }
 }
+
+// Foo.asm -> Foo.hack (or .bin, .hex, ... for other formats) in the same directory
+fn get_output_filename(input: &Path, format: Format) -> PathBuf {
+    input.with_extension(format.extension())
+}
@@ -0,0 +1,215 @@
+// peephole optimiser over the instructions before labels are resolved.
+//
+//     @SP, M=M+1, @SP, M=M-1    -> @SP             a push undone by a pop
+//     @SP, M=M+1, @SP, AM=M-1   -> @SP, A=M        a push directly popped again
+//     @X, ..., @X               -> @X, ...         A still holds X
+//     @X, @Y                    -> @Y              X is never used
+//     D=..., @X, D=...          -> @X, D=...       D is overwritten before it is read
+//
+// labels and directives end a sequence, as do jumps and writes to A for the
+// value held in A. instructions are never moved across them
+use std::rc::Rc;
+
+use crate::instruction::Instruction;
+use crate::parser::Line;
+
+// remove what the rules above allow until nothing changes. returns the new code
+// and the number of instructions saved in each file, in the order files appear
+pub fn optimize(code: Vec<Line>) -> (Vec<Line>, Vec<(Rc<str>, usize)>) {
+    let mut removed = vec![false; code.len()];
+    // lines whose AM=M-1 became A=M
+    let mut popped = vec![false; code.len()];
+    {
+        // decoded once. labels, directives and lines that do not decode, which are
+        // left for the assembler to report, are barriers
+        let mut instructions: Vec<Option<Instruction>> = code
+            .iter()
+            .map(|line| Instruction::decode(line).ok().filter(Instruction::is_word))
+            .collect();
+        while pass(&mut instructions, &mut removed, &mut popped) {}
+    }
+
+    let mut saved: Vec<(Rc<str>, usize)> = Vec::new();
+    let mut output = Vec::with_capacity(code.len());
+    for (index, mut line) in code.into_iter().enumerate() {
+        let file = line.origin().0;
+        let count = match saved.iter_mut().find(|(name, _)| name == file) {
+            Some((_, count)) => count,
+            None => {
+                saved.push((file.clone(), 0));
+                &mut saved.last_mut().unwrap().1
+            }
+        };
+        if removed[index] {
+            *count += 1;
+            continue;
+        }
+        if popped[index] {
+            line.text = "A=M".to_string();
+        }
+        output.push(line);
+    }
+    (output, saved)
+}
+
+// one sweep over the instructions not removed yet, returns whether it changed any
+fn pass(
+    instructions: &mut [Option<Instruction>],
+    removed: &mut [bool],
+    popped: &mut [bool],
+) -> bool {
+    let live: Vec<usize> = (0..instructions.len()).filter(|&i| !removed[i]).collect();
+    let code: Vec<Option<Instruction>> = live.iter().map(|&i| instructions[i]).collect();
+    let at = |k: usize| code.get(k).copied().flatten();
+    let mut remove = Vec::new();
+    let mut pop = Vec::new();
+
+    // what A holds, if known
+    let mut a: Option<&str> = None;
+    let mut k = 0;
+    while k < code.len() {
+        match code[k] {
+            None | Some(Instruction::Label(_) | Instruction::Directive { .. }) => a = None,
+            Some(Instruction::A(value)) => {
+                let pushed = value == "SP"
+                    && is_c(at(k + 1), "M", "M+1")
+                    && at(k + 2) == Some(Instruction::A("SP"));
+                if pushed && is_c(at(k + 3), "M", "M-1") {
+                    remove.extend([k + 1, k + 2, k + 3]);
+                    a = Some("SP");
+                    k += 4;
+                    continue;
+                }
+                if pushed && is_c(at(k + 3), "AM", "M-1") {
+                    remove.extend([k + 1, k + 2]);
+                    pop.push(k + 3);
+                    a = None;
+                    k += 4;
+                    continue;
+                }
+
+                if a == Some(value) {
+                    remove.push(k);
+                } else if let Some(Instruction::A(_)) = at(k + 1) {
+                    remove.push(k);
+                } else {
+                    a = Some(value);
+                }
+            }
+            Some(Instruction::C { dest, jump, .. }) => {
+                if dest == Some("D") && jump.is_none() && overwrites_d(&code[k + 1..]) {
+                    remove.push(k);
+                } else if dest.is_some_and(|dest| dest.contains('A')) || jump.is_some() {
+                    a = None;
+                }
+            }
+        }
+        k += 1;
+    }
+
+    for &k in &remove {
+        removed[live[k]] = true;
+    }
+    for &k in &pop {
+        let i = live[k];
+        instructions[i] = Some(Instruction::C {
+            dest: Some("A"),
+            comp: "M",
+            jump: None,
+        });
+        popped[i] = true;
+    }
+    !remove.is_empty()
+}
+
+// dest=comp without a jump
+fn is_c(instruction: Option<Instruction>, dest: &str, comp: &str) -> bool {
+    instruction
+        == Some(Instruction::C {
+            dest: Some(dest),
+            comp,
+            jump: None,
+        })
+}
+
+// whether D is written before it is read by the instructions that follow
+fn overwrites_d(rest: &[Option<Instruction>]) -> bool {
+    for instruction in rest {
+        match instruction {
+            Some(Instruction::A(_)) => continue,
+            Some(Instruction::C { dest, comp, jump }) => {
+                if comp.contains('D') || jump.is_some() {
+                    return false;
+                }
+                if dest.is_some_and(|dest| dest.contains('D')) {
+                    return true;
+                }
+            }
+            _ => return false,
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::read_lines;
+
+    fn optimized(source: &str) -> Vec<String> {
+        let (code, _) = optimize(read_lines("test.asm", source));
+        code.into_iter().map(|line| line.text).collect()
+    }
+
+    fn unchanged(source: &str) {
+        assert_eq!(optimized(source), source.lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn push_pop_cancel() {
+        let source = "@SP\nM=M+1\n@SP\nM=M-1\nD=M\n";
+        assert_eq!(optimized(source), ["@SP", "D=M"]);
+        let (_, saved) = optimize(read_lines("test.asm", source));
+        assert_eq!(saved, [(Rc::from("test.asm"), 3)]);
+    }
+
+    #[test]
+    fn push_popped_again() {
+        let source = "@SP\nM=M+1\n@SP\nAM=M-1\nD=M\n";
+        assert_eq!(optimized(source), ["@SP", "A=M", "D=M"]);
+    }
+
+    #[test]
+    fn redundant_a() {
+        assert_eq!(optimized("@X\nD=M\n@X\nM=D+1\n"), ["@X", "D=M", "M=D+1"]);
+        assert_eq!(optimized("@X\n@Y\nD=M\n"), ["@Y", "D=M"]);
+    }
+
+    #[test]
+    fn dead_d() {
+        assert_eq!(optimized("D=M\n@X\nD=A\nM=D\n"), ["@X", "D=A", "M=D"]);
+    }
+
+    #[test]
+    fn label_is_a_barrier() {
+        unchanged("@X\nD=M\n(L)\n@X\nM=D");
+        unchanged("D=M\n(L)\nD=A\n@X\nM=D");
+    }
+
+    #[test]
+    fn jump_is_a_barrier() {
+        unchanged("@X\nD;JGT\n@X\nM=D");
+        unchanged("D=M\n@L\n0;JMP\nD=A\n@X\nM=D");
+    }
+
+    #[test]
+    fn d_read_is_kept() {
+        unchanged("D=M\n@X\nM=D\nD=A\n@Y\nM=D");
+        unchanged("D=M\n@X\nD=D+A\n@Y\nM=D");
+    }
+
+    #[test]
+    fn a_written_is_reloaded() {
+        unchanged("@X\nA=M\n@X\nM=D");
+    }
+}
@@ -0,0 +1,130 @@
+// encodings of the assembled ROM words
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // the classic .hack text, one 16 character binary word per line
+    Hack,
+    // raw words, 2 bytes each
+    BinaryLittleEndian,
+    BinaryBigEndian,
+    // Intel HEX with byte addresses, each word stored high byte first
+    IntelHex,
+    // Verilog $readmemb image
+    Memb,
+    // Logisim "v2.0 raw" image, one hex word per line
+    Logisim,
+}
+
+pub const FORMATS: [(&str, Format); 6] = [
+    ("hack", Format::Hack),
+    ("bin-le", Format::BinaryLittleEndian),
+    ("bin-be", Format::BinaryBigEndian),
+    ("ihex", Format::IntelHex),
+    ("memb", Format::Memb),
+    ("logisim", Format::Logisim),
+];
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        FORMATS
+            .iter()
+            .find(|(format, _)| *format == name)
+            .map(|(_, format)| *format)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Hack => "hack",
+            Format::BinaryLittleEndian | Format::BinaryBigEndian => "bin",
+            Format::IntelHex => "hex",
+            Format::Memb => "mem",
+            Format::Logisim => "rom",
+        }
+    }
+
+    // the whole image in memory, see `write` for large programs
+    pub fn encode(&self, words: &[u16]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write(words, &mut bytes)
+            .expect("writing to memory cannot fail");
+        bytes
+    }
+
+    // stream the image to `out` without building it in memory first
+    pub fn write<W: Write>(&self, words: &[u16], out: &mut W) -> io::Result<()> {
+        match self {
+            Format::Hack => {
+                for (index, word) in words.iter().enumerate() {
+                    if index > 0 {
+                        out.write_all(b"\n")?;
+                    }
+                    out.write_all(&binary(*word))?;
+                }
+            }
+            Format::BinaryLittleEndian => {
+                for word in words {
+                    out.write_all(&word.to_le_bytes())?;
+                }
+            }
+            Format::BinaryBigEndian => {
+                for word in words {
+                    out.write_all(&word.to_be_bytes())?;
+                }
+            }
+            Format::IntelHex => intel_hex(words, out)?,
+            Format::Memb => {
+                out.write_all(b"// ROM image, load with $readmemb\n")?;
+                for (address, word) in words.iter().enumerate() {
+                    out.write_all(&binary(*word))?;
+                    writeln!(out, " // {:04X}", address)?;
+                }
+            }
+            Format::Logisim => {
+                out.write_all(b"v2.0 raw\n")?;
+                for word in words {
+                    writeln!(out, "{:04x}", word)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// the 16 binary digits of a word, without going through the formatter
+fn binary(word: u16) -> [u8; 16] {
+    let mut digits = [b'0'; 16];
+    for (bit, digit) in digits.iter_mut().enumerate() {
+        if word >> (15 - bit) & 1 == 1 {
+            *digit = b'1';
+        }
+    }
+    digits
+}
+
+// data records of 16 bytes followed by the end of file record.
+// 32K words are exactly 64KB, so no extended address record is needed
+fn intel_hex<W: Write>(words: &[u16], out: &mut W) -> io::Result<()> {
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+
+    for (index, chunk) in bytes.chunks(16).enumerate() {
+        let address = (index * 16) as u16;
+        let mut record = vec![chunk.len() as u8];
+        record.extend_from_slice(&address.to_be_bytes());
+        record.push(0x00);
+        record.extend_from_slice(chunk);
+        out.write_all(hex_record(&record).as_bytes())?;
+    }
+
+    out.write_all(hex_record(&[0x00, 0x00, 0x00, 0x01]).as_bytes())
+}
+
+fn hex_record(record: &[u8]) -> String {
+    let sum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    let mut line = String::from(":");
+    for byte in record {
+        line += &format!("{:02X}", byte);
+    }
+    line += &format!("{:02X}\n", sum.wrapping_neg());
+    line
+}
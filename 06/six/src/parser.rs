@@ -1,7 +1,375 @@
-pub fn parse_filename(args: &[String]) -> Result<String, &'static str> {
-    if args.len() < 2 {
-        return Err("not enought argument");
+use std::fs;
+use std::ops::Deref;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::error::AsmError;
+use crate::instruction::Instruction;
+use crate::{expr, include, macros, optimize, pseudo};
+
+pub struct Parser {
+    pub filename: String,
+    code: Vec<Line>,
+}
+
+// a source line with the comment and surrounding spaces removed
+#[derive(Clone)]
+pub struct Line {
+    pub text: String,
+    // the file the line was read from, included files keep their own name
+    pub file: Rc<str>,
+    // 1-based line number in the original file
+    pub number: usize,
+    // 1-based column where `text` starts
+    pub column: usize,
+    // the line as it appears in the file
+    pub source: Source,
+    // set when the line comes from a macro body, `number` is then the line in the body
+    pub expansion: Option<Expansion>,
+    // the VM command the line was translated from, from the last "// vm:" comment
+    pub vm: Option<Rc<str>>,
+    // offset and length of each file scope localize_labels put in `text`, the file
+    // does not have them
+    pub scopes: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expansion {
+    // the macro whose body the line comes from
+    pub name: String,
+    // file and line of the outermost macro call
+    pub file: Rc<str>,
+    pub line: usize,
+}
+
+// a range of the text of a file, which all its lines share. lines rewritten by a
+// macro hold their new text instead
+#[derive(Clone)]
+pub struct Source {
+    text: Rc<str>,
+    start: usize,
+    end: usize,
+}
+
+impl Source {
+    pub fn new(text: &str) -> Source {
+        Source {
+            text: Rc::from(text),
+            start: 0,
+            end: text.len(),
+        }
+    }
+}
+
+impl Deref for Source {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text[self.start..self.end]
+    }
+}
+
+impl Line {
+    // error covering `part`, which must be a slice of `text`
+    pub fn error_at(&self, part: &str, message: String) -> AsmError {
+        let start = part.as_ptr() as usize - self.text.as_ptr() as usize;
+        let end = self.written_offset(start + part.len());
+        let start = self.written_offset(start);
+        let columns = (self.column + start, self.column + end.max(start + 1));
+        let error =
+            AsmError::at(&self.file, self.number, columns, message).with_source(&self.source);
+        match &self.expansion {
+            Some(expansion) => error.with_note(format!(
+                "in expansion of macro {} called at {}:{}",
+                expansion.name, expansion.file, expansion.line
+            )),
+            None => error,
+        }
+    }
+
+    // error covering the whole line
+    pub fn error(&self, message: String) -> AsmError {
+        self.error_at(&self.text, message)
+    }
+
+    // line and column of `part`, which must be a slice of `text`
+    pub fn position_of(&self, part: &str) -> (usize, usize) {
+        let start = part.as_ptr() as usize - self.text.as_ptr() as usize;
+        (self.number, self.column + self.written_offset(start))
+    }
+
+    // `text` as written, without the file scopes of its $-symbols
+    pub fn written(&self) -> String {
+        let mut text = self.text.clone();
+        for &(at, len) in self.scopes.iter().rev() {
+            text.replace_range(at..at + len, "");
+        }
+        text
+    }
+
+    // where `offset` in `text` is in the text as written
+    fn written_offset(&self, offset: usize) -> usize {
+        self.scopes.iter().fold(offset, |written, &(at, len)| {
+            written - len.min(offset.saturating_sub(at))
+        })
+    }
+
+    // file and line the instruction stands on, the macro call for expanded lines
+    pub fn origin(&self) -> (&Rc<str>, usize) {
+        match &self.expansion {
+            Some(expansion) => (&expansion.file, expansion.line),
+            None => (&self.file, self.number),
+        }
+    }
+}
+
+impl Parser {
+    pub fn new(filename: &Path) -> Result<Parser, AsmError> {
+        let name = filename.display().to_string();
+        match fs::read_to_string(filename) {
+            Ok(source) => Ok(Parser::from_source(&name, &source)),
+            Err(e) => Err(AsmError::new(&name, format!("cannot open file: {}", e))),
+        }
+    }
+
+    // parse assembly held in memory, `filename` is used in error messages and
+    // as the directory .include paths are relative to
+    pub fn from_source(filename: &str, source: &str) -> Parser {
+        Parser {
+            filename: filename.to_string(),
+            code: read_lines(filename, source),
+        }
+    }
+
+    // put the lines of `other` after ours, to assemble several files into one program
+    pub fn append(&mut self, mut other: Parser) {
+        self.code.append(&mut other.code);
+    }
+
+    // replace .include directives by the lines of the included files
+    pub fn expand_includes(&mut self) -> Vec<AsmError> {
+        let code = std::mem::take(&mut self.code);
+        let (code, errors) = include::expand(code);
+        self.code = code;
+        errors
+    }
+
+    // $-prefixed labels are private to their file: $LOOP in Mult.asm becomes Mult.asm$LOOP
+    pub fn localize_labels(&mut self) {
+        for line in &mut self.code {
+            if !line.text.contains('$') {
+                continue;
+            }
+            // every operand that may name a symbol, so no directive misses the rename.
+            // lines that do not decode are left for the assembler to report
+            let operand = match Instruction::decode(line) {
+                Ok(Instruction::A(_) | Instruction::Label(_)) => true,
+                Ok(Instruction::Directive { name, .. }) => name != ".string",
+                Ok(Instruction::C { .. }) | Err(_) => false,
+            };
+            if !operand {
+                continue;
+            }
+
+            let scope = local_scope(&line.file);
+            let local = |symbol: &str| {
+                if symbol.starts_with('$') {
+                    Some(format!("{}{}", scope, symbol))
+                } else {
+                    None
+                }
+            };
+            // only `text` changes, errors and the listing show the line as written
+            let mut previous = None;
+            for (offset, c) in line.text.char_indices() {
+                if c == '$' && !previous.is_some_and(expr::is_symbol_char) {
+                    let at = offset + line.scopes.len() * scope.len();
+                    line.scopes.push((at, scope.len()));
+                }
+                previous = Some(c);
+            }
+            line.text = expr::rename_symbols(&line.text, &local);
+        }
+    }
+
+    // replace macro definitions and calls by the instructions they stand for
+    pub fn expand_macros(&mut self) -> Vec<AsmError> {
+        let code = std::mem::take(&mut self.code);
+        let (code, errors) = macros::expand(code);
+        self.code = code;
+        errors
+    }
+
+    // replace pseudo-instructions such as push D by their Hack sequences
+    pub fn expand_pseudo(&mut self) -> Vec<AsmError> {
+        let code = std::mem::take(&mut self.code);
+        let (code, errors) = pseudo::expand(code);
+        self.code = code;
+        errors
+    }
+
+    // run the peephole optimiser, returning the instructions saved per file
+    pub fn optimize(&mut self) -> Vec<(Rc<str>, usize)> {
+        let code = std::mem::take(&mut self.code);
+        let (code, saved) = optimize::optimize(code);
+        self.code = code;
+        saved
+    }
+
+    // every line after the expansions
+    pub fn lines(&self) -> &[Line] {
+        &self.code
+    }
+}
+
+// a decimal constant such as the 5 in @5
+pub fn is_constant(symbol: &str) -> bool {
+    symbol.bytes().all(|b| b.is_ascii_digit())
+}
+
+// the first word of `text` and the rest, trimmed: ".equ N 5" -> (".equ", "N 5")
+pub fn split_first_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], text[index..].trim()),
+        None => (text, ""),
+    }
+}
+
+// what $-labels of `file` are prefixed with: the file name made a valid symbol.
+// 04-mult.asm becomes _04_mult.asm, symbols cannot start with a digit
+pub fn local_scope(file: &str) -> String {
+    let name = Path::new(file)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let scope: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "_.:".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if scope.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", scope)
+    } else {
+        scope
+    }
+}
+
+// where the comment of `line` starts, a "//" in double quotes is part of a string
+pub fn comment_start(line: &str) -> usize {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '/' if !quoted && line[index..].starts_with("//") => return index,
+            _ => (),
+        }
+    }
+    line.len()
+}
+
+// every non-empty line with its comment stripped
+pub fn read_lines(filename: &str, source: &str) -> Vec<Line> {
+    let file: Rc<str> = Rc::from(filename);
+    let contents: Rc<str> = Rc::from(source);
+    let mut buf = Vec::new();
+    let mut vm: Option<Rc<str>> = None;
+
+    for (number, line) in source.lines().enumerate() {
+        let right = comment_start(line);
+        // "// vm: Main.vm:3: push constant 1" written by the VM translator
+        if let Some(origin) = line[right..].strip_prefix("//").map(str::trim) {
+            if let Some(origin) = origin.strip_prefix("vm:") {
+                vm = Some(Rc::from(origin.trim()));
+            }
+        }
+
+        let text = line[0..right].trim();
+
+        if text.is_empty() {
+            continue;
+        }
+        let column = line.find(text).unwrap_or(0) + 1;
+        let start = line.as_ptr() as usize - source.as_ptr() as usize;
+        buf.push(Line {
+            text: String::from(text),
+            file: Rc::clone(&file),
+            number: number + 1,
+            column,
+            source: Source {
+                text: Rc::clone(&contents),
+                start,
+                end: start + line.len(),
+            },
+            expansion: None,
+            vm: vm.clone(),
+            scopes: Vec::new(),
+        });
+    }
+
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_is_a_symbol() {
+        assert_eq!(local_scope("dir/Mult.asm"), "Mult.asm");
+        assert_eq!(local_scope("04-mult.asm"), "_04_mult.asm");
+        assert_eq!(local_scope("dir/1st.asm"), "_1st.asm");
+        assert!(expr::is_symbol(&format!("{}$LOOP", local_scope("1st.asm"))));
+    }
+
+    #[test]
+    fn labels_in_a_file_named_with_a_digit() {
+        let mut parser = Parser::from_source("1st.asm", "($L)\n@$L\n0;JMP\n");
+        parser.localize_labels();
+        let lines: Vec<&str> = parser
+            .lines()
+            .iter()
+            .map(|line| line.text.as_str())
+            .collect();
+        assert_eq!(lines, ["(_1st.asm$L)", "@_1st.asm$L", "0;JMP"]);
+    }
+
+    #[test]
+    fn comments_outside_strings() {
+        assert_eq!(comment_start("D=M // c"), 4);
+        assert_eq!(comment_start("D=M"), 3);
+        assert_eq!(comment_start(r#".string "a\"//b" // c"#), 17);
+
+        let source = ".string \"http://x.org\" // c\n.include \"a//b.asm\"\n";
+        let lines: Vec<String> = read_lines("a.asm", source)
+            .into_iter()
+            .map(|line| line.text)
+            .collect();
+        assert_eq!(lines, [".string \"http://x.org\"", ".include \"a//b.asm\""]);
+    }
+
+    #[test]
+    fn localized_lines_keep_their_source() {
+        let mut parser = Parser::from_source("a.asm", "  @$L+1 // back\n");
+        parser.localize_labels();
+        let line = &parser.lines()[0];
+        assert_eq!(line.text, "@a.asm$L+1");
+        assert_eq!(line.written(), "@$L+1");
+        assert_eq!(&*line.source, "  @$L+1 // back");
+        assert_eq!(line.column, 3);
+
+        // errors point at the symbol as written
+        let error = line.error_at(&line.text[1..], "message".to_string());
+        assert_eq!(error.columns, (4, 8));
+        let error = line.error_at(&line.text[9..], "message".to_string());
+        assert_eq!(error.columns, (7, 8));
+        assert_eq!(line.position_of(&line.text[1..]), (1, 4));
     }
-    let filename = args[1].clone();
-    return Ok(filename);
 }
@@ -0,0 +1,228 @@
+// pseudo-instructions standing for the usual Hack sequences.
+//
+//     push X               D=X, @SP, A=M, M=D, @SP, M=M+1   (X is a comp, D=X is left out for D)
+//     pop X                @SP, AM=M-1, X=M                  (X is A, D or AD)
+//     mov RAM[x], RAM[y]   @y, D=M, @x, M=D                  (either side may be a register,
+//                                                            or the source a constant)
+//     jmp label            @label, 0;JMP
+//     jz D, label          @label, D;JEQ                     (also jnz, jgt, jge, jlt and jle)
+//     ld D, value          @value, D=A
+//
+// x, y, label and value are anything an A-instruction accepts. the generated lines
+// keep the source of the pseudo-instruction, errors in an operand point into it
+use crate::code;
+use crate::error::AsmError;
+use crate::parser::{split_first_word, Line};
+
+// how each pseudo-instruction is written, for errors
+const USAGE: [(&str, &str); 5] = [
+    ("push", "push D"),
+    ("pop", "pop D"),
+    ("mov", "mov RAM[x], RAM[y]"),
+    ("jmp", "jmp label"),
+    ("ld", "ld D, value"),
+];
+
+// conditional jumps and the jump mnemonic they use
+const CONDITIONS: [(&str, &str); 6] = [
+    ("jz", "JEQ"),
+    ("jnz", "JNE"),
+    ("jgt", "JGT"),
+    ("jge", "JGE"),
+    ("jlt", "JLT"),
+    ("jle", "JLE"),
+];
+
+pub fn is_pseudo(text: &str) -> bool {
+    let name = split_first_word(text).0;
+    USAGE.iter().any(|(pseudo, _)| *pseudo == name)
+        || CONDITIONS.iter().any(|(condition, _)| *condition == name)
+}
+
+// a pseudo-instruction that fails is dropped, the other lines are kept
+pub fn expand(code: Vec<Line>) -> (Vec<Line>, Vec<AsmError>) {
+    let mut output = Vec::new();
+    let mut errors = Vec::new();
+
+    for line in code {
+        if !is_pseudo(&line.text) {
+            output.push(line);
+            continue;
+        }
+        let mut expansion = Vec::new();
+        match expand_line(&line, &mut expansion) {
+            Ok(()) => output.extend(expansion),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    (output, errors)
+}
+
+fn expand_line(line: &Line, output: &mut Vec<Line>) -> Result<(), AsmError> {
+    let (name, operands) = split_first_word(&line.text);
+    if operands.is_empty() {
+        let usage = match USAGE.iter().find(|(pseudo, _)| *pseudo == name) {
+            Some((_, usage)) => usage.to_string(),
+            None => format!("{} D, label", name),
+        };
+        return Err(line.error_at(&line.text, format!("expected '{}'", usage)));
+    }
+    let mut emit =
+        |text: String, operand: Option<&str>| output.push(generated(line, text, operand));
+
+    match name {
+        "push" => {
+            let value = comp(line, operands, "push")?;
+            if value != "D" {
+                emit(format!("D={}", value), Some(value));
+            }
+            emit("@SP".to_string(), None);
+            emit("A=M".to_string(), None);
+            emit("M=D".to_string(), None);
+            emit("@SP".to_string(), None);
+            emit("M=M+1".to_string(), None);
+        }
+        "pop" => {
+            let register = register(line, operands, "pop")?;
+            emit("@SP".to_string(), None);
+            emit("AM=M-1".to_string(), None);
+            emit(format!("{}=M", register), Some(register));
+        }
+        "jmp" => {
+            if operands.contains(',') {
+                return Err(line.error_at(&line.text, "expected 'jmp label'".to_string()));
+            }
+            let target = operands;
+            emit(format!("@{}", target), Some(target));
+            emit("0;JMP".to_string(), None);
+        }
+        "ld" => {
+            let (register, value) = pair(line, operands, "ld D, value")?;
+            let register = self::register(line, register, "ld")?;
+            emit(format!("@{}", value), Some(value));
+            if register != "A" {
+                emit(format!("{}=A", register), Some(register));
+            }
+        }
+        "mov" => {
+            let (destination, source) = pair(line, operands, "mov RAM[x], RAM[y]")?;
+            match (ram(source), ram(destination)) {
+                (Some(from), Some(to)) => {
+                    emit(format!("@{}", from), Some(from));
+                    emit("D=M".to_string(), None);
+                    emit(format!("@{}", to), Some(to));
+                    emit("M=D".to_string(), None);
+                }
+                (Some(from), None) => {
+                    let register = register(line, destination, "mov")?;
+                    emit(format!("@{}", from), Some(from));
+                    emit(format!("{}=M", register), Some(register));
+                }
+                // a value the ALU cannot compute is a constant, as in ld
+                (None, Some(to)) if code::comp(source).is_none() => {
+                    emit(format!("@{}", source), Some(source));
+                    emit("D=A".to_string(), None);
+                    emit(format!("@{}", to), Some(to));
+                    emit("M=D".to_string(), None);
+                }
+                (None, Some(to)) => {
+                    let value = comp(line, source, "mov")?;
+                    // A is replaced by the address, so values using A or M go through D
+                    if value.contains(['A', 'M']) {
+                        emit(format!("D={}", value), Some(value));
+                        emit(format!("@{}", to), Some(to));
+                        emit("M=D".to_string(), None);
+                    } else {
+                        emit(format!("@{}", to), Some(to));
+                        emit(format!("M={}", value), Some(value));
+                    }
+                }
+                (None, None) => {
+                    let register = register(line, destination, "mov")?;
+                    let value = comp(line, source, "mov")?;
+                    emit(format!("{}={}", register, value), Some(value));
+                }
+            }
+        }
+        _ => {
+            let jump = CONDITIONS
+                .iter()
+                .find(|(condition, _)| *condition == name)
+                .map(|(_, jump)| *jump)
+                .unwrap();
+            let usage = format!("{} D, label", name);
+            let (value, target) = pair(line, operands, &usage)?;
+            let value = comp(line, value, name)?;
+            if value.contains(['A', 'M']) {
+                let message = format!("'{}' cannot test A or M, the jump target replaces A", name);
+                return Err(line.error_at(value, message));
+            }
+            emit(format!("@{}", target), Some(target));
+            emit(format!("{};{}", value, jump), None);
+        }
+    }
+    Ok(())
+}
+
+// a line of the expansion. `operand` is a slice of the pseudo-instruction that the
+// text ends with, the column is set so errors about it point into the original line
+fn generated(line: &Line, text: String, operand: Option<&str>) -> Line {
+    let column = match operand {
+        Some(operand) => {
+            let start = operand.as_ptr() as usize - line.text.as_ptr() as usize;
+            let offset = text.rfind(operand).unwrap_or(0);
+            line.column + start - offset
+        }
+        None => line.column,
+    };
+    Line {
+        text,
+        column,
+        ..line.clone()
+    }
+}
+
+fn pair<'a>(line: &Line, operands: &'a str, usage: &str) -> Result<(&'a str, &'a str), AsmError> {
+    match operands.split_once(',') {
+        Some((first, second)) if !first.trim().is_empty() && !second.trim().is_empty() => {
+            Ok((first.trim(), second.trim()))
+        }
+        _ => Err(line.error_at(&line.text, format!("expected '{}'", usage))),
+    }
+}
+
+// a value the ALU can compute, such as D, M+1 or 0
+fn comp<'a>(line: &Line, operand: &'a str, name: &str) -> Result<&'a str, AsmError> {
+    match code::comp(operand) {
+        Some(_) => Ok(operand),
+        None => {
+            let message = format!(
+                "'{}' cannot take '{}', expected a comp such as D, M or 0",
+                name, operand
+            );
+            Err(line.error_at(operand, message))
+        }
+    }
+}
+
+// A, D or AD, M is not allowed as A holds an address of the sequence
+fn register<'a>(line: &Line, operand: &'a str, name: &str) -> Result<&'a str, AsmError> {
+    match code::dest(operand) {
+        Some(_) if !operand.contains('M') => Ok(operand),
+        _ => {
+            let message = format!("'{}' cannot write '{}', expected A, D or AD", name, operand);
+            Err(line.error_at(operand, message))
+        }
+    }
+}
+
+// the address x of RAM[x]
+fn ram(operand: &str) -> Option<&str> {
+    let address = operand.strip_prefix("RAM[")?.strip_suffix(']')?.trim();
+    if address.is_empty() {
+        None
+    } else {
+        Some(address)
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub struct SymbolTable {
+    table: HashMap<String, Symbol>,
+}
+
+// the order of the variants is the order of the exported symbol map
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SymbolKind {
+    Label,
+    // defined with .equ
+    Constant,
+    // defined with .data
+    Data,
+    Variable,
+    Predefined,
+}
+
+impl SymbolKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolKind::Label => "label",
+            SymbolKind::Constant => "constant",
+            SymbolKind::Data => "data",
+            SymbolKind::Variable => "variable",
+            SymbolKind::Predefined => "predefined",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub address: usize,
+    pub kind: SymbolKind,
+    // where the symbol was defined, empty and 0 for predefined symbols
+    pub file: Rc<str>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Symbol {
+    pub fn new(
+        address: usize,
+        kind: SymbolKind,
+        file: Rc<str>,
+        line: usize,
+        column: usize,
+    ) -> Symbol {
+        Symbol {
+            address,
+            kind,
+            file,
+            line,
+            column,
+        }
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        let mut new_table = SymbolTable {
+            table: HashMap::new(),
+        };
+
+        new_table.predefine("SP", 0);
+        new_table.predefine("LCL", 1);
+        new_table.predefine("ARG", 2);
+        new_table.predefine("THIS", 3);
+        new_table.predefine("THAT", 4);
+        new_table.predefine("R0", 0);
+        new_table.predefine("R1", 1);
+        new_table.predefine("R2", 2);
+        new_table.predefine("R3", 3);
+        new_table.predefine("R4", 4);
+        new_table.predefine("R5", 5);
+        new_table.predefine("R6", 6);
+        new_table.predefine("R7", 7);
+        new_table.predefine("R8", 8);
+        new_table.predefine("R9", 9);
+        new_table.predefine("R10", 10);
+        new_table.predefine("R11", 11);
+        new_table.predefine("R12", 12);
+        new_table.predefine("R13", 13);
+        new_table.predefine("R14", 14);
+        new_table.predefine("R15", 15);
+        new_table.predefine("SCREEN", 16384);
+        new_table.predefine("KBD", 24576);
+
+        new_table
+    }
+
+    fn predefine(&mut self, symbol: &str, address: usize) {
+        let entry = Symbol::new(address, SymbolKind::Predefined, Rc::from(""), 0, 0);
+        self.table.insert(symbol.to_string(), entry);
+    }
+
+    // a symbol is only defined once, on conflict the existing entry is returned
+    pub fn add_entry(&mut self, symbol: String, entry: Symbol) -> Result<(), &Symbol> {
+        if self.contains(&symbol) {
+            return Err(&self.table[&symbol]);
+        }
+        self.table.insert(symbol, entry);
+        Ok(())
+    }
+
+    pub fn contains(&self, symbol: &str) -> bool {
+        self.table.contains_key(symbol)
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Symbol> {
+        self.table.get(symbol)
+    }
+
+    // labels (ROM), constants, then data, variables and predefined symbols (RAM), each by address
+    pub fn sorted(&self) -> Vec<(&String, &Symbol)> {
+        let mut entries: Vec<(&String, &Symbol)> = self.table.iter().collect();
+        entries.sort_by(|a, b| (a.1.kind, a.1.address, a.0).cmp(&(b.1.kind, b.1.address, b.0)));
+        entries
+    }
+
+    // one "name kind address" line per symbol
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# name kind address\n");
+        for (name, symbol) in self.sorted() {
+            text += &format!("{} {} {}\n", name, symbol.kind.name(), symbol.address);
+        }
+        text
+    }
+
+    // symbol names only contain [A-Za-z0-9_.$:], so they need no escaping
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .sorted()
+            .into_iter()
+            .map(|(name, symbol)| {
+                format!(
+                    "  {{\"name\": \"{}\", \"kind\": \"{}\", \"address\": {}, \"predefined\": {}}}",
+                    name,
+                    symbol.kind.name(),
+                    symbol.address,
+                    symbol.kind == SymbolKind::Predefined
+                )
+            })
+            .collect();
+        format!("[\n{}\n]\n", entries.join(",\n"))
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> SymbolTable {
+        SymbolTable::new()
+    }
+}
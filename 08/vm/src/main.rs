@@ -1,4 +1,5 @@
 use std::env;
+use std::fmt;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
@@ -6,93 +7,529 @@ use std::io::BufReader;
 use std::io::BufWriter;
 use std::path::Path;
 use std::path::PathBuf;
+use std::process;
 
-static GENERIC_0: &'static str = "13";
+static GENERIC_0: &str = "13";
 
-fn print_type<T>(_: &T) {
-    println!("{}", std::any::type_name::<T>())
-}
+const USAGE: &str =
+    "usage: vm [-o output.asm] [--bootstrap | --no-bootstrap] <file.vm | directory>...";
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let input_path = get_input_path(&args).unwrap();
-    let output_file = get_output_filename(&input_path);
-
-    let mut writer = CodeWriter::new(&output_file).unwrap();
-
-    let input_path = Path::new(&input_path);
+    let config = match Config::new(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(1);
+        }
+    };
 
-    let files = input_path
-        .read_dir()
-        .unwrap()
-        .map(|x| x.unwrap().path())
-        .filter(|x| x.extension().unwrap() == "vm")
-        .collect::<Vec<PathBuf>>();
+    let files = match collect_files(&config.inputs) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let output_file = match &config.output {
+        Some(output) => output.clone(),
+        None => get_output_filename(&config.inputs, &files),
+    };
 
     for file in &files {
-        print!("{:?}\n", file);
+        println!("{:?}", file);
     }
 
-    if files.len() > 1 {
-        writer.write_init();
+    // every file is parsed before anything is written, all errors are reported at once
+    let mut parsers = Vec::new();
+    let mut errors = Vec::new();
+    for file in files {
+        match Parser::new(&file) {
+            Ok(parser) => parsers.push((file, parser)),
+            Err(mut e) => errors.append(&mut e),
+        };
+    }
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+        eprintln!("{} error(s)", errors.len());
+        process::exit(1);
     }
 
-    for file in files {
-        let mut parser = Parser::new(&file).unwrap();
+    let mut writer = match CodeWriter::new(&output_file) {
+        Ok(writer) => writer,
+        Err(e) => {
+            eprintln!("cannot create {}: {}", output_file.display(), e);
+            process::exit(1);
+        }
+    };
+
+    // by default a program gets the bootstrap code when it has a Sys.init to call
+    let has_sys_init = parsers
+        .iter()
+        .any(|(_, parser)| parser.defines_function("Sys.init"));
+    let bootstrap = config.bootstrap.unwrap_or(has_sys_init);
+    if bootstrap && !has_sys_init {
+        eprintln!("warning: the bootstrap code calls Sys.init, which no file defines");
+    }
+    if bootstrap {
+        writer.write_init();
+    }
 
+    for (file, mut parser) in parsers {
+        writer.set_file_name(&file);
+        // the file name alone, so the output does not depend on how the path was typed
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
         while parser.has_more_commands() {
-            let command: &String = parser.get_command();
-            writer.write_down(&format!("// {}\n", command));
-            match parser.command_type() {
-                CommandType::CPOP => writer.write_pushpop("pop", parser.arg1(), parser.arg2()),
-                CommandType::CPUSH => writer.write_pushpop("push", parser.arg1(), parser.arg2()),
-                CommandType::CARITHMETIC => writer.write_arithmetic(parser.arg1()),
-                CommandType::CLABEL => writer.write_label(parser.arg1()),
-                CommandType::CGOTO => writer.write_goto(parser.arg1()),
-                CommandType::CIF => writer.write_if(parser.arg1()),
-                CommandType::CFUNCTION => writer.write_function(parser.arg1(), parser.arg2()),
-                CommandType::CCALL => writer.write_call(parser.arg1(), parser.arg2()),
-                CommandType::CRETURN => writer.write_return(),
+            // the assembler reads these back to map ROM addresses to VM commands
+            let origin = format!(
+                "// vm: {}:{}: {}\n",
+                name,
+                parser.line_number(),
+                parser.get_command()
+            );
+            writer.write_down(&origin);
+            match parser.command() {
+                VmCommand::Arithmetic(operation) => writer.write_arithmetic(*operation),
+                VmCommand::Push { segment, index } => writer.push(*segment, *index),
+                VmCommand::Pop { segment, index } => writer.pop(*segment, *index),
+                VmCommand::Label(label) => writer.write_label(label),
+                VmCommand::Goto(label) => writer.write_goto(label),
+                VmCommand::IfGoto(label) => writer.write_if(label),
+                VmCommand::Function { name, nlocals } => writer.write_function(name, *nlocals),
+                VmCommand::Call { name, nargs } => writer.write_call(name, *nargs),
+                VmCommand::Return => writer.write_return(),
             };
             parser.advance();
         }
-        writer.file_countup();
     }
 }
 
-fn get_input_path(args: &[String]) -> Result<String, &str> {
-    if args.len() < 2 {
-        return Err("not enought argument");
+struct Config {
+    // .vm files and directories of them, translated into one program
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    // write the bootstrap code calling Sys.init, None to write it if Sys.init is defined
+    bootstrap: Option<bool>,
+}
+
+impl Config {
+    fn new(args: &[String]) -> Result<Config, String> {
+        let mut inputs = Vec::new();
+        let mut output = None;
+        let mut bootstrap = None;
+
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" => match args.next() {
+                    Some(path) => output = Some(PathBuf::from(path)),
+                    None => return Err("-o requires an output path".to_string()),
+                },
+                "--bootstrap" => bootstrap = Some(true),
+                "--no-bootstrap" => bootstrap = Some(false),
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+                _ => inputs.push(PathBuf::from(arg)),
+            }
+        }
+
+        if inputs.is_empty() {
+            return Err("no input file".to_string());
+        }
+        Ok(Config {
+            inputs,
+            output,
+            bootstrap,
+        })
+    }
+}
+
+// the .vm files in the order they are named on the command line, the files of a
+// directory sorted by name so the output does not depend on the file system
+fn collect_files(inputs: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+
+    for input in inputs {
+        if input.is_dir() {
+            let entries = match input.read_dir() {
+                Ok(entries) => entries,
+                Err(e) => return Err(format!("cannot read {}: {}", input.display(), e)),
+            };
+            let mut found = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "vm"))
+                .collect::<Vec<PathBuf>>();
+            if found.is_empty() {
+                return Err(format!("no .vm file in {}", input.display()));
+            }
+            found.sort();
+            files.append(&mut found);
+        } else if input.extension().is_none_or(|ext| ext != "vm") {
+            return Err(format!("{} is not a .vm file", input.display()));
+        } else if !input.is_file() {
+            return Err(format!("cannot read {}: no such file", input.display()));
+        } else {
+            files.push(input.clone());
+        }
     }
-    let filename = args[1].clone();
-    return Ok(filename);
+
+    // a file named twice, directly and through its directory, is translated once
+    let mut seen = Vec::new();
+    files.retain(|file| {
+        let canonical = file.canonicalize().unwrap_or_else(|_| file.clone());
+        if seen.contains(&canonical) {
+            return false;
+        }
+        seen.push(canonical);
+        true
+    });
+    Ok(files)
+}
+
+// the directory Prog gives Prog/Prog.asm, files the name of the first one next to it
+fn get_output_filename(inputs: &[PathBuf], files: &[PathBuf]) -> PathBuf {
+    match inputs {
+        [dir] if dir.is_dir() => {
+            let name = dir
+                .canonicalize()
+                .ok()
+                .and_then(|dir| dir.file_name().map(|name| name.to_owned()));
+            match name {
+                Some(name) => dir.join(name).with_extension("asm"),
+                None => files[0].with_extension("asm"),
+            }
+        }
+        _ => files[0].with_extension("asm"),
+    }
+}
+
+// an error in a .vm file, line is 0 for errors about the whole file
+#[derive(Debug)]
+struct VmError {
+    file: String,
+    line: usize,
+    message: String,
 }
 
-fn get_output_filename(filename: &str) -> String {
-    let filename = Path::new(filename).file_stem().unwrap().to_str().unwrap();
-    let output_file = format!("{}.asm", filename);
-    output_file
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: error: {}", self.file, self.message)
+        } else {
+            write!(f, "{}:{}: error: {}", self.file, self.line, self.message)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Segment {
+    Argument,
+    Local,
+    Static,
+    Constant,
+    This,
+    That,
+    Pointer,
+    Temp,
+}
+
+const SEGMENTS: [(&str, Segment); 8] = [
+    ("argument", Segment::Argument),
+    ("local", Segment::Local),
+    ("static", Segment::Static),
+    ("constant", Segment::Constant),
+    ("this", Segment::This),
+    ("that", Segment::That),
+    ("pointer", Segment::Pointer),
+    ("temp", Segment::Temp),
+];
+
+impl Segment {
+    fn from_name(name: &str) -> Option<Segment> {
+        SEGMENTS
+            .iter()
+            .find(|(segment, _)| *segment == name)
+            .map(|(_, segment)| *segment)
+    }
+
+    fn name(self) -> &'static str {
+        SEGMENTS
+            .iter()
+            .find(|(_, segment)| *segment == self)
+            .map(|(name, _)| *name)
+            .unwrap()
+    }
+
+    // the largest index the segment has: temp is R5-R12, pointer THIS and THAT,
+    // statics RAM[16..256] and constants the 15 bits of an A-instruction
+    fn max_index(self) -> u16 {
+        match self {
+            Segment::Temp => 7,
+            Segment::Pointer => 1,
+            Segment::Static => 239,
+            _ => 32767,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Arithmetic {
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+}
+
+const ARITHMETIC: [(&str, Arithmetic); 9] = [
+    ("add", Arithmetic::Add),
+    ("sub", Arithmetic::Sub),
+    ("neg", Arithmetic::Neg),
+    ("eq", Arithmetic::Eq),
+    ("gt", Arithmetic::Gt),
+    ("lt", Arithmetic::Lt),
+    ("and", Arithmetic::And),
+    ("or", Arithmetic::Or),
+    ("not", Arithmetic::Not),
+];
+
+impl Arithmetic {
+    fn from_name(name: &str) -> Option<Arithmetic> {
+        ARITHMETIC
+            .iter()
+            .find(|(operation, _)| *operation == name)
+            .map(|(_, operation)| *operation)
+    }
+}
+
+// a command of a .vm file with its operands checked
+#[derive(Debug, Clone, PartialEq)]
+enum VmCommand {
+    Arithmetic(Arithmetic),
+    Push { segment: Segment, index: u16 },
+    Pop { segment: Segment, index: u16 },
+    Label(String),
+    Goto(String),
+    IfGoto(String),
+    Function { name: String, nlocals: u16 },
+    Call { name: String, nargs: u16 },
+    Return,
+}
+
+// how each command is written, for errors about its operands
+const USAGE_OF: [(&str, &str); 8] = [
+    ("push", "push segment index"),
+    ("pop", "pop segment index"),
+    ("label", "label name"),
+    ("goto", "goto label"),
+    ("if-goto", "if-goto label"),
+    ("function", "function name nlocals"),
+    ("call", "call name nargs"),
+    ("return", "return"),
+];
+
+impl VmCommand {
+    // keywords must match exactly, "pushy" is not push
+    fn parse(text: &str) -> Result<VmCommand, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let command = match words.as_slice() {
+            ["push", segment, index] => {
+                let segment = segment_of(segment)?;
+                let index = index_of(segment, index)?;
+                VmCommand::Push { segment, index }
+            }
+            ["pop", "constant", _] => {
+                return Err("cannot pop into the constant segment".to_string())
+            }
+            ["pop", segment, index] => {
+                let segment = segment_of(segment)?;
+                let index = index_of(segment, index)?;
+                VmCommand::Pop { segment, index }
+            }
+            ["label", label] => VmCommand::Label(symbol(label, "label")?),
+            ["goto", label] => VmCommand::Goto(symbol(label, "label")?),
+            ["if-goto", label] => VmCommand::IfGoto(symbol(label, "label")?),
+            ["function", name, nlocals] => VmCommand::Function {
+                name: symbol(name, "function name")?,
+                nlocals: count(nlocals, "local variables")?,
+            },
+            ["call", name, nargs] => VmCommand::Call {
+                name: symbol(name, "function name")?,
+                nargs: count(nargs, "arguments")?,
+            },
+            ["return"] => VmCommand::Return,
+            [name, operands @ ..] => match (Arithmetic::from_name(name), operands) {
+                (Some(operation), []) => VmCommand::Arithmetic(operation),
+                (Some(_), _) => return Err(format!("'{}' takes no operands", name)),
+                (None, _) => return Err(misuse(name)),
+            },
+            [] => return Err("empty command".to_string()),
+        };
+        Ok(command)
+    }
+}
+
+// a known command with the wrong operands, or an unknown one
+fn misuse(name: &str) -> String {
+    match USAGE_OF.iter().find(|(keyword, _)| *keyword == name) {
+        Some((_, usage)) => format!("expected '{}'", usage),
+        None => format!("unknown command '{}'", name),
+    }
+}
+
+fn segment_of(name: &str) -> Result<Segment, String> {
+    match Segment::from_name(name) {
+        Some(segment) => Ok(segment),
+        None => Err(format!(
+            "unknown segment '{}', expected argument, local, static, constant, this, that, pointer or temp",
+            name
+        )),
+    }
+}
+
+fn index_of(segment: Segment, index: &str) -> Result<u16, String> {
+    if !index.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("invalid index '{}'", index));
+    }
+    let max = segment.max_index();
+    match index.parse::<u16>() {
+        Ok(value) if value <= max => Ok(value),
+        _ => Err(format!(
+            "{} index {} is out of range (0..={})",
+            segment.name(),
+            index,
+            max
+        )),
+    }
+}
+
+// the number of locals of a function or of arguments of a call
+fn count(text: &str, what: &str) -> Result<u16, String> {
+    match text.parse::<u16>() {
+        Ok(count) if count <= 32767 => Ok(count),
+        _ => Err(format!("invalid number of {} '{}'", what, text)),
+    }
+}
+
+// labels and function names are letters, digits, '_', '.' and ':', not starting with a digit
+fn symbol(name: &str, what: &str) -> Result<String, String> {
+    let valid = !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.:".contains(c));
+    if valid {
+        Ok(name.to_string())
+    } else {
+        Err(format!("invalid {} '{}'", what, name))
+    }
+}
+
+// every goto and if-goto must name a label of its own function, labels outside
+// any function belong to the code outside functions of the file
+fn check_labels(file: &str, commands: &[(usize, String, VmCommand)]) -> Vec<VmError> {
+    let mut labels: Vec<(Option<&str>, &str, usize)> = Vec::new();
+    let mut gotos: Vec<(Option<&str>, &str, usize)> = Vec::new();
+    let mut errors = Vec::new();
+    let mut function: Option<&str> = None;
+    let error = |line: usize, message: String| VmError {
+        file: file.to_string(),
+        line,
+        message,
+    };
+
+    for (line, _, command) in commands {
+        match command {
+            VmCommand::Function { name, .. } => function = Some(name),
+            VmCommand::Label(label) => {
+                let previous = labels
+                    .iter()
+                    .find(|(scope, name, _)| *scope == function && name == label);
+                match previous {
+                    Some((_, _, previous)) => {
+                        let message = format!(
+                            "duplicate label '{}' in {}, first defined on line {}",
+                            label,
+                            scope_name(function),
+                            previous
+                        );
+                        errors.push(error(*line, message));
+                    }
+                    None => labels.push((function, label, *line)),
+                }
+            }
+            VmCommand::Goto(label) | VmCommand::IfGoto(label) => {
+                gotos.push((function, label, *line))
+            }
+            _ => (),
+        }
+    }
+
+    for (function, label, line) in gotos {
+        if labels
+            .iter()
+            .any(|(scope, name, _)| *scope == function && *name == label)
+        {
+            continue;
+        }
+        let message = match labels.iter().find(|(_, name, _)| *name == label) {
+            Some((scope, _, _)) => format!(
+                "label '{}' is in {}, outside {}",
+                label,
+                scope_name(*scope),
+                scope_name(function)
+            ),
+            None => format!("undefined label '{}' in {}", label, scope_name(function)),
+        };
+        errors.push(error(line, message));
+    }
+
+    errors.sort_by_key(|e| e.line);
+    errors
+}
+
+fn scope_name(function: Option<&str>) -> String {
+    match function {
+        Some(function) => format!("function {}", function),
+        None => "the code outside functions".to_string(),
+    }
 }
 
 struct Parser {
-    commands: Vec<String>,
+    // each command with its 1-based line number and text
+    commands: Vec<(usize, String, VmCommand)>,
     command_pos: usize,
 }
 
 impl Parser {
-    pub fn new(filename: &PathBuf) -> Result<Parser, &str> {
+    // a parser over the commands of a file, or every error found in it
+    pub fn new(filename: &PathBuf) -> Result<Parser, Vec<VmError>> {
+        let file = filename.display().to_string();
         let f = match File::open(filename) {
             Ok(f) => f,
-            Err(_) => {
-                println!("{}", filename.to_str().unwrap());
-                return Err("cannot open file !!");
+            Err(e) => {
+                let message = format!("cannot open file: {}", e);
+                return Err(vec![VmError {
+                    file,
+                    line: 0,
+                    message,
+                }]);
             }
         };
 
         let reader = BufReader::new(f);
         let mut buf = Vec::new();
+        let mut errors = Vec::new();
 
-        for line in reader.lines() {
+        for (number, line) in reader.lines().enumerate() {
             let line = match line {
                 Ok(s) => s,
                 Err(_) => continue,
@@ -106,13 +543,26 @@ impl Parser {
 
             let line = line[..right].trim();
 
-            if line.len() == 0 {
+            if line.is_empty() {
                 continue;
             };
 
-            buf.push(String::from(line));
+            match VmCommand::parse(line) {
+                Ok(command) => buf.push((number + 1, String::from(line), command)),
+                Err(message) => errors.push(VmError {
+                    file: file.clone(),
+                    line: number + 1,
+                    message,
+                }),
+            }
         }
 
+        // the labels of the commands that parsed are checked as well
+        errors.append(&mut check_labels(&file, &buf));
+        if !errors.is_empty() {
+            errors.sort_by_key(|e| e.line);
+            return Err(errors);
+        }
         Ok(Parser {
             commands: buf,
             command_pos: 0,
@@ -120,11 +570,7 @@ impl Parser {
     }
 
     pub fn has_more_commands(&self) -> bool {
-        if self.command_pos < self.commands.len() {
-            return true;
-        } else {
-            return false;
-        }
+        self.command_pos < self.commands.len()
     }
 
     pub fn advance(&mut self) {
@@ -134,119 +580,94 @@ impl Parser {
         self.command_pos += 1;
     }
 
+    // the current command as written in the file
     pub fn get_command(&self) -> &String {
-        &self.commands[self.command_pos]
-    }
-
-    pub fn command_type(&self) -> CommandType {
-        let command = self.get_command();
-
-        if command.starts_with("push") {
-            return CommandType::CPUSH;
-        } else if command.starts_with("pop") {
-            return CommandType::CPOP;
-        } else if command.starts_with("label") {
-            return CommandType::CLABEL;
-        } else if command.starts_with("goto") {
-            return CommandType::CGOTO;
-        } else if command.starts_with("if") {
-            return CommandType::CIF;
-        } else if command.starts_with("function") {
-            return CommandType::CFUNCTION;
-        } else if command.starts_with("call") {
-            return CommandType::CCALL;
-        } else if command.starts_with("return") {
-            return CommandType::CRETURN;
-        } else {
-            return CommandType::CARITHMETIC;
-        }
+        &self.commands[self.command_pos].1
     }
 
-    pub fn arg1(&self) -> &str {
-        let command = self.get_command();
-        let morpheme: Vec<&str> = command.split_whitespace().collect();
+    pub fn command(&self) -> &VmCommand {
+        &self.commands[self.command_pos].2
+    }
 
-        match self.command_type() {
-            CommandType::CRETURN => panic!("arg1 is called while comamnd type is 'return'"),
-            CommandType::CARITHMETIC => morpheme[0],
-            _ => morpheme[1],
-        }
+    pub fn line_number(&self) -> usize {
+        self.commands[self.command_pos].0
     }
 
-    pub fn arg2(&self) -> &str {
-        match self.command_type() {
-            CommandType::CARITHMETIC => panic!("arg2 is called while command type is arithmetic"),
-            CommandType::CLABEL => panic!("arg2 is called while command type is 'label'"),
-            CommandType::CGOTO => panic!("arg2 is called while command type is 'goto'"),
-            CommandType::CIF => panic!("arg2 is called while command type is 'if'"),
-            CommandType::CRETURN => panic!("arg2 is called while command type is 'return'"),
-            _ => {
-                let command = &self.commands[self.command_pos];
-                let morpheme: Vec<&str> = command.split_whitespace().collect();
-                morpheme[2]
-            }
-        }
+    // whether the file has a "function name n" command
+    pub fn defines_function(&self, name: &str) -> bool {
+        self.commands.iter().any(|(_, _, command)| {
+            matches!(command, VmCommand::Function { name: function, .. } if function == name)
+        })
     }
 }
 
-enum CommandType {
-    CARITHMETIC,
-    CPUSH,
-    CPOP,
-    CLABEL,
-    CGOTO,
-    CIF,
-    CFUNCTION,
-    CRETURN,
-    CCALL,
+// the register holding the base address of local, argument, this and that
+fn base_register(segment: Segment) -> &'static str {
+    match segment {
+        Segment::Local => "LCL",
+        Segment::Argument => "ARG",
+        Segment::This => "THIS",
+        Segment::That => "THAT",
+        _ => unreachable!("{} has no base register", segment.name()),
+    }
 }
 
 struct CodeWriter {
     output_file: BufWriter<File>,
     jmp_point: i64,
     return_num: i64,
-    file_count: i32,
+    // the file being translated, statics are named after it: Main.3
+    file_name: String,
+    // the function being translated, its labels are named after it: Main.main$LOOP
+    function_name: String,
 }
 
 impl CodeWriter {
-    pub fn new(filename: &str) -> Result<CodeWriter, io::Error> {
+    pub fn new(filename: &Path) -> Result<CodeWriter, io::Error> {
         let writer = BufWriter::new(File::create(filename)?);
         Ok(CodeWriter {
             output_file: writer,
             jmp_point: 0,
             return_num: 0,
-            file_count: 0,
+            file_name: String::new(),
+            function_name: String::new(),
         })
     }
 
-    pub fn file_countup(&mut self) {
-        self.file_count += 1;
+    // Main.vm -> Main, characters an assembler symbol cannot have become '_'
+    pub fn set_file_name(&mut self, file: &Path) {
+        self.function_name.clear();
+        let stem = file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.file_name = stem
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || "_.$:".contains(c) {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
     }
 
     pub fn write_down(&mut self, command: &str) {
-        self.output_file.write(command.as_bytes()).unwrap();
-    }
-
-    pub fn write_arithmetic(&mut self, command: &str) {
-        match command {
-            "add" => self.arithmetic_add(),
-            "sub" => self.arithmetic_sub(),
-            "neg" => self.arithmetic_neg(),
-            "eq" => self.arithmetic_eq(),
-            "gt" => self.arithmetic_gt(),
-            "lt" => self.arithmetic_lt(),
-            "and" => self.arithmetic_and(),
-            "or" => self.arithmetic_or(),
-            "not" => self.arithmetic_not(),
-            _ => panic!("{} is not a arithmetic command !!", command),
-        }
+        self.output_file.write_all(command.as_bytes()).unwrap();
     }
 
-    pub fn write_pushpop(&mut self, command: &str, segment: &str, index: &str) {
-        match command {
-            "push" => self.push(segment, index),
-            "pop" => self.pop(segment, index),
-            _ => panic!("{} is not push pop command !!", command),
+    pub fn write_arithmetic(&mut self, operation: Arithmetic) {
+        match operation {
+            Arithmetic::Add => self.arithmetic_add(),
+            Arithmetic::Sub => self.arithmetic_sub(),
+            Arithmetic::Neg => self.arithmetic_neg(),
+            Arithmetic::Eq => self.arithmetic_eq(),
+            Arithmetic::Gt => self.arithmetic_gt(),
+            Arithmetic::Lt => self.arithmetic_lt(),
+            Arithmetic::And => self.arithmetic_and(),
+            Arithmetic::Or => self.arithmetic_or(),
+            Arithmetic::Not => self.arithmetic_not(),
         }
     }
 
@@ -267,51 +688,38 @@ impl CodeWriter {
         self.write_down(assembly_code);
     }
 
-    fn push(&mut self, segment: &str, index: &str) {
-        let data_position = {
-            if segment == "constant" {
-                format!(
-                    "@{}\
-                    \nD=A",
-                    index
-                )
-            } else if segment == "temp" {
-                format!(
-                    "@{}\
-                    \nD=M",
-                    5 + index.parse::<i32>().unwrap()
-                )
-            } else if segment == "pointer" {
-                format!(
-                    "@{}\
-                    \nD=M",
-                    3 + index.parse::<i32>().unwrap()
-                )
-            } else if segment == "static" {
-                format!(
-                    "@{}_{}\
-                    \nD=M",
-                    self.file_count, index
-                )
-            } else {
-                let segment = match segment {
-                    "local" => "LCL",
-                    "argument" => "ARG",
-                    "this" => "THIS",
-                    "that" => "THAT",
-                    "temp" => "5",
-                    _ => panic!("segment not match "),
-                };
-                format!(
-                    "@{}\
-                    \nD=M\
-                    \n@{}\
-                    \nD=D+A\
-                    \nA=D\
-                    \nD=M",
-                    segment, index
-                )
-            }
+    pub fn push(&mut self, segment: Segment, index: u16) {
+        let data_position = match segment {
+            Segment::Constant => format!(
+                "@{}\
+                \nD=A",
+                index
+            ),
+            Segment::Temp => format!(
+                "@{}\
+                \nD=M",
+                5 + index
+            ),
+            Segment::Pointer => format!(
+                "@{}\
+                \nD=M",
+                3 + index
+            ),
+            Segment::Static => format!(
+                "@{}.{}\
+                \nD=M",
+                self.file_name, index
+            ),
+            Segment::Local | Segment::Argument | Segment::This | Segment::That => format!(
+                "@{}\
+                \nD=M\
+                \n@{}\
+                \nD=D+A\
+                \nA=D\
+                \nD=M",
+                base_register(segment),
+                index
+            ),
         };
 
         let assembly_code = format!(
@@ -326,8 +734,8 @@ impl CodeWriter {
         self.write_down(&assembly_code);
     }
 
-    fn pop(&mut self, segment: &str, index: &str) {
-        if segment == "temp" {
+    pub fn pop(&mut self, segment: Segment, index: u16) {
+        if segment == Segment::Temp {
             let assembly_code = format!(
                 "@SP\
                 \nM=M-1\
@@ -335,13 +743,13 @@ impl CodeWriter {
                 \nD=M\
                 \n@{}\
                 \nM=D\n\n",
-                index.parse::<i32>().unwrap() + 5
+                index + 5
             );
             self.write_down(&assembly_code);
             return;
         }
 
-        if segment == "pointer" {
+        if segment == Segment::Pointer {
             let assembly_code = format!(
                 "@SP\
                 \nM=M-1\
@@ -349,34 +757,26 @@ impl CodeWriter {
                 \nD=M\
                 \n@{}\
                 \nM=D\n\n",
-                index.parse::<i32>().unwrap() + 3
+                index + 3
             );
             self.write_down(&assembly_code);
             return;
         }
 
-        if segment == "static" {
+        if segment == Segment::Static {
             let assembly_code = format!(
                 "@SP\
                 \nM=M-1\
                 \nA=M\
                 \nD=M\
-                \n@{}_{}\
+                \n@{}.{}\
                 \nM=D\n\n",
-                self.file_count, index
+                self.file_name, index
             );
             self.write_down(&assembly_code);
             return;
         }
 
-        let segment = match segment {
-            "local" => "LCL",
-            "argument" => "ARG",
-            "this" => "THIS",
-            "that" => "THAT",
-            _ => panic!("segment not match "),
-        };
-
         let assembly_code = format!(
             "@{0}\
             \nD=M\
@@ -391,7 +791,9 @@ impl CodeWriter {
             \n@{2}\
             \nA=M\
             \nM=D\n\n",
-            segment, index, GENERIC_0
+            base_register(segment),
+            index,
+            GENERIC_0
         );
         self.write_down(&assembly_code);
     }
@@ -448,11 +850,20 @@ impl CodeWriter {
             \n@SP\
             \nM=D\n";
         self.write_down(assembly_code);
-        self.write_call("Sys.init", "0");
+        self.write_call("Sys.init", 0);
+    }
+
+    // labels are local to their function, outside functions to the file: Main$LOOP
+    fn label_name(&self, label: &str) -> String {
+        if self.function_name.is_empty() {
+            format!("{}${}", self.file_name, label)
+        } else {
+            format!("{}${}", self.function_name, label)
+        }
     }
 
     pub fn write_label(&mut self, label: &str) {
-        let assembly_code = format!("({})\n\n", label);
+        let assembly_code = format!("({})\n\n", self.label_name(label));
         self.write_down(&assembly_code)
     }
 
@@ -461,7 +872,7 @@ impl CodeWriter {
             "@{}\
             \n0;JMP\
             \n\n",
-            label
+            self.label_name(label)
         );
         self.write_down(&assembly_code)
     }
@@ -472,12 +883,12 @@ impl CodeWriter {
             "@{}
             \nD;JNE\
             \n\n",
-            label
+            self.label_name(label)
         );
         self.write_down(&assembly_code)
     }
 
-    pub fn write_call(&mut self, functionname: &str, numargs: &str) {
+    pub fn write_call(&mut self, functionname: &str, numargs: u16) {
         // push return-address
         let return_label = format!("{}_{}", functionname, self.return_num);
         self.return_num += 1;
@@ -594,7 +1005,7 @@ impl CodeWriter {
         // ARG = * (FRAME - 3)
         let assembly_code = "\
             @13\
-            \nM=M-1;
+            \nM=M-1\
             \nA=M\
             \nD=M\
             \n@ARG\
@@ -617,14 +1028,15 @@ impl CodeWriter {
         self.write_down(assembly_code);
     }
 
-    pub fn write_function(&mut self, function: &str, num_of_locals: &str) {
+    pub fn write_function(&mut self, function: &str, num_of_locals: u16) {
+        self.function_name = function.to_string();
         // (f)
         let assembly = format!("({})\n", function);
         self.write_down(&assembly);
         // repeat k times: push0
         let assembly = "D=0\n";
         self.write_down(assembly);
-        for _ in 0..num_of_locals.parse::<i32>().unwrap() {
+        for _ in 0..num_of_locals {
             self.write_push_from_d_register();
         }
         let assembly = "\n";
@@ -688,3 +1100,165 @@ impl CodeWriter {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn error(text: &str) -> String {
+        VmCommand::parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            VmCommand::parse("push  local 2"),
+            Ok(VmCommand::Push {
+                segment: Segment::Local,
+                index: 2
+            })
+        );
+        assert_eq!(
+            VmCommand::parse("pop temp 7"),
+            Ok(VmCommand::Pop {
+                segment: Segment::Temp,
+                index: 7
+            })
+        );
+        assert_eq!(
+            VmCommand::parse("add"),
+            Ok(VmCommand::Arithmetic(Arithmetic::Add))
+        );
+        assert_eq!(
+            VmCommand::parse("if-goto Main.loop:1"),
+            Ok(VmCommand::IfGoto("Main.loop:1".to_string()))
+        );
+        assert_eq!(
+            VmCommand::parse("function Main.main 3"),
+            Ok(VmCommand::Function {
+                name: "Main.main".to_string(),
+                nlocals: 3
+            })
+        );
+        assert_eq!(VmCommand::parse("return"), Ok(VmCommand::Return));
+    }
+
+    #[test]
+    fn keywords_match_exactly() {
+        assert_eq!(error("pushy constant 1"), "unknown command 'pushy'");
+        assert_eq!(error("ifx"), "unknown command 'ifx'");
+        assert_eq!(error("Add"), "unknown command 'Add'");
+    }
+
+    #[test]
+    fn index_limits() {
+        assert!(VmCommand::parse("push temp 7").is_ok());
+        assert_eq!(error("push temp 8"), "temp index 8 is out of range (0..=7)");
+        assert!(VmCommand::parse("pop pointer 1").is_ok());
+        assert_eq!(
+            error("pop pointer 2"),
+            "pointer index 2 is out of range (0..=1)"
+        );
+        assert!(VmCommand::parse("push static 239").is_ok());
+        assert_eq!(
+            error("push static 240"),
+            "static index 240 is out of range (0..=239)"
+        );
+        assert!(VmCommand::parse("push constant 32767").is_ok());
+        assert_eq!(
+            error("push constant 32768"),
+            "constant index 32768 is out of range (0..=32767)"
+        );
+        assert_eq!(
+            error("push constant 99999"),
+            "constant index 99999 is out of range (0..=32767)"
+        );
+        assert_eq!(error("push local -1"), "invalid index '-1'");
+        assert_eq!(error("push local x"), "invalid index 'x'");
+    }
+
+    #[test]
+    fn pop_constant() {
+        assert_eq!(
+            error("pop constant 0"),
+            "cannot pop into the constant segment"
+        );
+    }
+
+    #[test]
+    fn operand_counts() {
+        assert_eq!(error("push constant"), "expected 'push segment index'");
+        assert_eq!(error("pop local 1 2"), "expected 'pop segment index'");
+        assert_eq!(error("goto"), "expected 'goto label'");
+        assert_eq!(
+            error("function Main.main"),
+            "expected 'function name nlocals'"
+        );
+        assert_eq!(error("return 1"), "expected 'return'");
+        assert_eq!(error("add 1"), "'add' takes no operands");
+        assert_eq!(
+            error("call Main.f 40000"),
+            "invalid number of arguments '40000'"
+        );
+        assert_eq!(
+            error("function Main.f -1"),
+            "invalid number of local variables '-1'"
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert!(error("push heap 0").starts_with("unknown segment 'heap'"));
+        assert_eq!(error("label 1st"), "invalid label '1st'");
+        assert_eq!(error("goto a-b"), "invalid label 'a-b'");
+        assert_eq!(error("call Main$f 0"), "invalid function name 'Main$f'");
+    }
+
+    #[test]
+    fn files_named_twice() {
+        let dir = env::temp_dir().join(format!("vm-collect-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Main.vm"), "return\n").unwrap();
+        fs::write(dir.join("Sys.vm"), "return\n").unwrap();
+
+        let inputs = [
+            dir.join("Main.vm"),
+            dir.clone(),
+            dir.join(".").join("Sys.vm"),
+        ];
+        let files = collect_files(&inputs);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files, Ok(vec![dir.join("Main.vm"), dir.join("Sys.vm")]));
+    }
+
+    #[test]
+    fn labels() {
+        let source = [
+            "label A",
+            "goto B",
+            "function F 0",
+            "label A",
+            "label A",
+            "goto C",
+            "if-goto A",
+        ];
+        let commands: Vec<(usize, String, VmCommand)> = source
+            .iter()
+            .enumerate()
+            .map(|(index, text)| (index + 1, text.to_string(), VmCommand::parse(text).unwrap()))
+            .collect();
+        let messages: Vec<String> = check_labels("T.vm", &commands)
+            .into_iter()
+            .map(|e| format!("{}: {}", e.line, e.message))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "2: undefined label 'B' in the code outside functions",
+                "5: duplicate label 'A' in function F, first defined on line 4",
+                "6: undefined label 'C' in function F",
+            ]
+        );
+    }
+}